@@ -5,6 +5,11 @@ mod fields {
         pub const ENQUEUE_ITEM: &'static str = "enqueue_item";
         pub const PLAY_ITEM: &'static str = "play_item";
         pub const SKIP: &'static str = "skip";
+        pub const BOOTH: &'static str = "booth";
+        pub const VOTES: &'static str = "votes";
+        pub const PLAYED: &'static str = "played";
+        pub const DEQUEUE_ITEM: &'static str = "dequeue_item";
+        pub const MOVE_ITEM: &'static str = "move_item";
     }
 
     pub mod ingress_message {
@@ -15,6 +20,32 @@ mod fields {
         pub const DJ_QUEUE: &'static str = "dj_queue";
         pub const DJ_UNQUEUE: &'static str = "dj_unqueue";
         pub const MESSAGE: &'static str = "message";
+        pub const CLOCK: &'static str = "clock";
+        pub const AUTHENTICATE: &'static str = "authenticate";
+        pub const NICK: &'static str = "nick";
+        pub const SET_ROLE: &'static str = "set_role";
+        pub const KICK: &'static str = "kick";
+        pub const MUTE: &'static str = "mute";
+        pub const UNMUTE: &'static str = "unmute";
+        pub const BAN: &'static str = "ban";
+        pub const UNBAN: &'static str = "unban";
+        pub const VOTE: &'static str = "vote";
+        pub const GRAB: &'static str = "grab";
+        pub const HISTORY: &'static str = "history";
+        pub const ENQUEUE: &'static str = "enqueue";
+        pub const DEQUEUE: &'static str = "dequeue";
+        pub const REORDER: &'static str = "reorder";
+        pub const PLAYLISTS: &'static str = "playlists";
+        pub const PLAYLIST_CREATE: &'static str = "playlist_create";
+        pub const PLAYLIST_RENAME: &'static str = "playlist_rename";
+        pub const PLAYLIST_DELETE: &'static str = "playlist_delete";
+        pub const PLAYLIST_ADD: &'static str = "playlist_add";
+        pub const PLAYLIST_REMOVE: &'static str = "playlist_remove";
+        pub const PLAYLIST_MOVE: &'static str = "playlist_move";
+        pub const PLAYLIST_SHUFFLE: &'static str = "playlist_shuffle";
+        pub const PLAYLIST_ACTIVATE: &'static str = "playlist_activate";
+        pub const PLAYLIST_IMPORT: &'static str = "playlist_import";
+        pub const PLAYLIST_EXPORT: &'static str = "playlist_export";
     }
 
     pub mod egress_message {
@@ -24,6 +55,17 @@ mod fields {
         pub const STATUS_MESSAGE: &'static str = "status_message";
         pub const USER_MESSAGE: &'static str = "user_message";
         pub const PLAYBACK_MESSAGE: &'static str = "playback_message";
+        pub const WELCOME: &'static str = "welcome";
+        pub const ERROR: &'static str = "error";
+        pub const ACK: &'static str = "ack";
+        pub const NACK: &'static str = "nack";
+        pub const NICK_CHANGE: &'static str = "nick_change";
+        pub const ROLE_CHANGE: &'static str = "role_change";
+        pub const MODERATION: &'static str = "moderation";
+        pub const HISTORY: &'static str = "history";
+        pub const PLAYLISTS: &'static str = "playlists";
+        pub const IMPORT_REPORT: &'static str = "import_report";
+        pub const PLAYLIST_EXPORT: &'static str = "playlist_export";
     }
 }
 
@@ -32,6 +74,7 @@ pub struct Join {
     pub when: u64,
     pub uid: super::UserId,
     pub nick: String,
+    pub role: Role,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -40,9 +83,45 @@ pub struct Part {
     pub uid: super::UserId,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NickChange {
+    pub when: u64,
+    pub uid: super::UserId,
+    pub old_nick: String,
+    pub new_nick: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RoleChange {
+    pub when: u64,
+    pub uid: super::UserId,
+    pub role: Role,
+    // who changed it
+    pub by: super::UserId,
+}
+
+/// Announces that a moderator has acted against a user.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Moderation {
+    pub when: u64,
+    pub action: ModAction,
+    // the user acted against
+    pub uid: super::UserId,
+    // the moderator; None when the server acted by itself
+    pub by: Option<super::UserId>,
+    // when a mute or ban runs out; None for permanent bans
+    pub until: Option<u64>,
+    pub reason: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Clock {
+    // when the server sent this message
     pub when: u64,
+    // the `when` of the ClockRequest being answered, if any
+    pub origin: Option<u64>,
+    // when the server received the ClockRequest being answered, if any
+    pub received: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -58,24 +137,631 @@ pub struct UserMessage {
     pub body: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserInfo {
+    pub uid: super::UserId,
+    // None for users who haven't registered
+    pub nick: Option<String>,
+    pub role: Role,
+}
+
+/// The state of the room, sent only to a client that has just connected.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Welcome {
+    pub when: u64,
+    // the id assigned to the receiving client
+    pub uid: super::UserId,
+    pub users: Vec<UserInfo>,
+    // the current DJ, if any
+    pub dj: Option<super::UserId>,
+    pub waitlist: Vec<super::UserId>,
+    pub now_playing: Option<PlayItem>,
+    // how far into `now_playing` we are, in milliseconds
+    pub elapsed: Option<u64>,
+    // the votes on `now_playing` so far
+    pub votes: Option<Votes>,
+    // reconnect with `?resume=<token>` to pick this session back up
+    pub resume_token: String,
+    // whether this connection picked up an earlier session
+    pub resumed: bool,
+}
+
+/// Sent only to the client whose request could not be carried out.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Error {
+    pub when: u64,
+    pub code: ErrorCode,
+    // human-readable explanation
+    pub message: String,
+    // the kind of request which failed (e.g. "dj_queue"), if known
+    pub request: Option<String>,
+}
+
+/// Confirms that the request with the given id was carried out.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Ack {
+    pub when: u64,
+    pub id: u64,
+}
+
+/// Reports that the request with the given id was refused.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Nack {
+    pub when: u64,
+    pub id: u64,
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ErrorCode {
+    // the frame could not be parsed as a request
+    Malformed,
+    // the frame or a field in it was too long
+    TooLarge,
+    // a field had a value we can't accept
+    Invalid,
+    // the user's role doesn't allow the request
+    Forbidden,
+    // the request refers to something which doesn't exist
+    NotFound,
+    // the request clashes with the current state of the room
+    Conflict,
+    // a queue or other room limit has been reached
+    LimitReached,
+    // the user is making requests too quickly
+    RateLimited,
+    // the token presented was not accepted
+    Unauthorized,
+    // something the server depends on is not working
+    Unavailable,
+}
+
+impl ErrorCode {
+    pub fn from_name(name: &str) -> Result<Self, ()> {
+        use self::ErrorCode as EC;
+        match name {
+            "malformed" => Ok(EC::Malformed),
+            "too_large" => Ok(EC::TooLarge),
+            "invalid" => Ok(EC::Invalid),
+            "forbidden" => Ok(EC::Forbidden),
+            "not_found" => Ok(EC::NotFound),
+            "conflict" => Ok(EC::Conflict),
+            "limit_reached" => Ok(EC::LimitReached),
+            "rate_limited" => Ok(EC::RateLimited),
+            "unauthorized" => Ok(EC::Unauthorized),
+            "unavailable" => Ok(EC::Unavailable),
+            _ => Err(()),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        use self::ErrorCode as EC;
+        match *self {
+            EC::Malformed => "malformed",
+            EC::TooLarge => "too_large",
+            EC::Invalid => "invalid",
+            EC::Forbidden => "forbidden",
+            EC::NotFound => "not_found",
+            EC::Conflict => "conflict",
+            EC::LimitReached => "limit_reached",
+            EC::RateLimited => "rate_limited",
+            EC::Unauthorized => "unauthorized",
+            EC::Unavailable => "unavailable",
+        }
+    }
+}
+
+impl serde::Serialize for ErrorCode {
+    #[inline]
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: serde::Serializer,
+    {
+        use serde::Serialize;
+        self.name().serialize(serializer)
+    }
+}
+
+impl serde::Deserialize for ErrorCode {
+    fn deserialize<D>(deserializer: &mut D) -> Result<ErrorCode, D::Error>
+        where D: serde::Deserializer,
+    {
+        deserializer.visit(ErrorCodeVisitor)
+    }
+}
+
+struct ErrorCodeVisitor;
+
+impl serde::de::Visitor for ErrorCodeVisitor {
+    type Value = ErrorCode;
+
+    fn visit_str<E>(&mut self, value: &str) -> Result<ErrorCode, E>
+        where E: serde::de::Error,
+    {
+        ErrorCode::from_name(value)
+            .map_err(|_e| E::syntax("expect an error code"))
+    }
+}
+
+/// A user's standing in a room, from least to most trusted. Each role can
+/// do whatever the roles below it can.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    // hasn't registered or authenticated
+    Guest,
+    User,
+    ResidentDj,
+    Bouncer,
+    Manager,
+    CoHost,
+    Host,
+}
+
+impl Role {
+    pub fn from_name(name: &str) -> Result<Self, ()> {
+        match name {
+            "guest" => Ok(Role::Guest),
+            "user" => Ok(Role::User),
+            "resident_dj" => Ok(Role::ResidentDj),
+            "bouncer" => Ok(Role::Bouncer),
+            "manager" => Ok(Role::Manager),
+            "co_host" => Ok(Role::CoHost),
+            "host" => Ok(Role::Host),
+            _ => Err(()),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Role::Guest => "guest",
+            Role::User => "user",
+            Role::ResidentDj => "resident_dj",
+            Role::Bouncer => "bouncer",
+            Role::Manager => "manager",
+            Role::CoHost => "co_host",
+            Role::Host => "host",
+        }
+    }
+}
+
+impl serde::Serialize for Role {
+    #[inline]
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: serde::Serializer,
+    {
+        use serde::Serialize;
+        self.name().serialize(serializer)
+    }
+}
+
+impl serde::Deserialize for Role {
+    fn deserialize<D>(deserializer: &mut D) -> Result<Role, D::Error>
+        where D: serde::Deserializer,
+    {
+        deserializer.visit(RoleVisitor)
+    }
+}
+
+struct RoleVisitor;
+
+impl serde::de::Visitor for RoleVisitor {
+    type Value = Role;
+
+    fn visit_str<E>(&mut self, value: &str) -> Result<Role, E>
+        where E: serde::de::Error,
+    {
+        Role::from_name(value)
+            .map_err(|_e| E::syntax("expect a role"))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Vote {
+    Woot,
+    Meh,
+}
+
+impl Vote {
+    pub fn from_name(name: &str) -> Result<Self, ()> {
+        match name {
+            "woot" => Ok(Vote::Woot),
+            "meh" => Ok(Vote::Meh),
+            _ => Err(()),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Vote::Woot => "woot",
+            Vote::Meh => "meh",
+        }
+    }
+}
+
+impl serde::Serialize for Vote {
+    #[inline]
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: serde::Serializer,
+    {
+        use serde::Serialize;
+        self.name().serialize(serializer)
+    }
+}
+
+impl serde::Deserialize for Vote {
+    fn deserialize<D>(deserializer: &mut D) -> Result<Vote, D::Error>
+        where D: serde::Deserializer,
+    {
+        deserializer.visit(VoteVisitor)
+    }
+}
+
+struct VoteVisitor;
+
+impl serde::de::Visitor for VoteVisitor {
+    type Value = Vote;
+
+    fn visit_str<E>(&mut self, value: &str) -> Result<Vote, E>
+        where E: serde::de::Error,
+    {
+        Vote::from_name(value)
+            .map_err(|_e| E::syntax("expect woot or meh"))
+    }
+}
+
+/// How a playlist is written out for import and export.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PlaylistFormat {
+    // a `Playlist`, as sent in `playlists` messages
+    Json,
+    // extended M3U
+    M3u,
+    Csv,
+}
+
+impl PlaylistFormat {
+    pub fn from_name(name: &str) -> Result<Self, ()> {
+        match name {
+            "json" => Ok(PlaylistFormat::Json),
+            "m3u" => Ok(PlaylistFormat::M3u),
+            "csv" => Ok(PlaylistFormat::Csv),
+            _ => Err(()),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            PlaylistFormat::Json => "json",
+            PlaylistFormat::M3u => "m3u",
+            PlaylistFormat::Csv => "csv",
+        }
+    }
+}
+
+impl serde::Serialize for PlaylistFormat {
+    #[inline]
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: serde::Serializer,
+    {
+        use serde::Serialize;
+        self.name().serialize(serializer)
+    }
+}
+
+impl serde::Deserialize for PlaylistFormat {
+    fn deserialize<D>(deserializer: &mut D) -> Result<PlaylistFormat, D::Error>
+        where D: serde::Deserializer,
+    {
+        deserializer.visit(PlaylistFormatVisitor)
+    }
+}
+
+struct PlaylistFormatVisitor;
+
+impl serde::de::Visitor for PlaylistFormatVisitor {
+    type Value = PlaylistFormat;
+
+    fn visit_str<E>(&mut self, value: &str) -> Result<PlaylistFormat, E>
+        where E: serde::de::Error,
+    {
+        PlaylistFormat::from_name(value)
+            .map_err(|_e| E::syntax("expect json, m3u or csv"))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ModAction {
+    Kick,
+    Mute,
+    Unmute,
+    Ban,
+    Unban,
+}
+
+impl ModAction {
+    pub fn from_name(name: &str) -> Result<Self, ()> {
+        match name {
+            "kick" => Ok(ModAction::Kick),
+            "mute" => Ok(ModAction::Mute),
+            "unmute" => Ok(ModAction::Unmute),
+            "ban" => Ok(ModAction::Ban),
+            "unban" => Ok(ModAction::Unban),
+            _ => Err(()),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ModAction::Kick => "kick",
+            ModAction::Mute => "mute",
+            ModAction::Unmute => "unmute",
+            ModAction::Ban => "ban",
+            ModAction::Unban => "unban",
+        }
+    }
+}
+
+impl serde::Serialize for ModAction {
+    #[inline]
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: serde::Serializer,
+    {
+        use serde::Serialize;
+        self.name().serialize(serializer)
+    }
+}
+
+impl serde::Deserialize for ModAction {
+    fn deserialize<D>(deserializer: &mut D) -> Result<ModAction, D::Error>
+        where D: serde::Deserializer,
+    {
+        deserializer.visit(ModActionVisitor)
+    }
+}
+
+struct ModActionVisitor;
+
+impl serde::de::Visitor for ModActionVisitor {
+    type Value = ModAction;
+
+    fn visit_str<E>(&mut self, value: &str) -> Result<ModAction, E>
+        where E: serde::de::Error,
+    {
+        ModAction::from_name(value)
+            .map_err(|_e| E::syntax("expect a moderation action"))
+    }
+}
+
+/// A track added to a DJ's queue.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct EnqueueItem {
     pub when: u64,
     pub uid: super::UserId,
-    pub yid: String,
+    // identifies the entry in the DJ's queue
+    pub eid: u64,
+    pub media: MediaRef,
+    pub info: MediaInfo,
+    // where in the DJ's queue it went, 0 being next up
+    pub position: usize,
 }
 
+/// A track taken out of a DJ's queue.
 #[derive(Debug, Serialize, Deserialize)]
+pub struct DequeueItem {
+    pub when: u64,
+    pub uid: super::UserId,
+    pub eid: u64,
+}
+
+/// A track moved within a DJ's queue.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MoveItem {
+    pub when: u64,
+    pub uid: super::UserId,
+    pub eid: u64,
+    pub position: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PlayItem {
+    // when playback started, or will start
     pub when: u64,
     pub uid: super::UserId,
-    pub yid: String,
+    // the DJ queue entry this came from
+    pub eid: u64,
+    pub media: MediaRef,
+    pub info: MediaInfo,
+}
+
+/// Where a track comes from: which provider, and its id there. Ids are
+/// video ids for youtube, track urls for soundcloud, urls of audio files
+/// for url, and library ids for library.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MediaRef {
+    pub provider: ProviderKind,
+    pub id: String,
+}
+
+/// What a provider knows about a track.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaInfo {
+    pub title: String,
+    pub artist: Option<String>,
+    // length of the track in milliseconds
+    pub duration: u64,
+    // url of an image to show while it plays
+    pub thumbnail: Option<String>,
+    // where the server streams the track from, for tracks in its library
+    pub stream_url: Option<String>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ProviderKind {
+    Youtube,
+    Soundcloud,
+    // a direct link to an audio file
+    Url,
+    // the server's own library
+    Library,
+}
+
+impl ProviderKind {
+    pub fn from_name(name: &str) -> Result<Self, ()> {
+        match name {
+            "youtube" => Ok(ProviderKind::Youtube),
+            "soundcloud" => Ok(ProviderKind::Soundcloud),
+            "url" => Ok(ProviderKind::Url),
+            "library" => Ok(ProviderKind::Library),
+            _ => Err(()),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ProviderKind::Youtube => "youtube",
+            ProviderKind::Soundcloud => "soundcloud",
+            ProviderKind::Url => "url",
+            ProviderKind::Library => "library",
+        }
+    }
+}
+
+impl serde::Serialize for ProviderKind {
+    #[inline]
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: serde::Serializer,
+    {
+        use serde::Serialize;
+        self.name().serialize(serializer)
+    }
+}
+
+impl serde::Deserialize for ProviderKind {
+    fn deserialize<D>(deserializer: &mut D) -> Result<ProviderKind, D::Error>
+        where D: serde::Deserializer,
+    {
+        deserializer.visit(ProviderKindVisitor)
+    }
+}
+
+struct ProviderKindVisitor;
+
+impl serde::de::Visitor for ProviderKindVisitor {
+    type Value = ProviderKind;
+
+    fn visit_str<E>(&mut self, value: &str) -> Result<ProviderKind, E>
+        where E: serde::de::Error,
+    {
+        ProviderKind::from_name(value)
+            .map_err(|_e| E::syntax("expect youtube, soundcloud, url or library"))
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Skip {
-    // who skipped
-    pub uid: super::UserId,
+    pub when: u64,
+    // who skipped; None when the room voted the track off
+    pub uid: Option<super::UserId>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Booth {
+    pub when: u64,
+    // who is currently playing, if anyone
+    pub dj: Option<super::UserId>,
+    // the DJs waiting for their turn, next up first
+    pub waitlist: Vec<super::UserId>,
+}
+
+/// The votes on the current play so far.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Votes {
+    pub when: u64,
+    // who just voted, if anyone
+    pub uid: Option<super::UserId>,
+    pub woots: u32,
+    pub mehs: u32,
+    pub grabs: u32,
+}
+
+/// How a play went, announced when it ends and kept in the room's history.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayRecord {
+    // when the play ended
+    pub when: u64,
+    pub item: PlayItem,
+    // whether it was cut short
+    pub skipped: bool,
+    pub woots: u32,
+    pub mehs: u32,
+    pub grabs: u32,
+}
+
+/// The room's most recent plays, oldest first; sent only to the client
+/// which asked.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct History {
+    pub when: u64,
+    pub plays: Vec<PlayRecord>,
+}
+
+/// A track in a playlist.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaylistItem {
+    pub media: MediaRef,
+    pub info: MediaInfo,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Playlist {
+    pub id: u64,
+    pub name: String,
+    // the track at the front is played next, then moved to the back
+    pub items: Vec<PlaylistItem>,
+}
+
+/// A track which couldn't be imported.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportFailure {
+    // where it was in the imported data, counting from 1
+    pub line: usize,
+    // the track as the data gave it
+    pub location: String,
+    pub reason: String,
+}
+
+/// How an import went; sent only to the client which asked.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub when: u64,
+    // whether this was only a trial, leaving the playlists untouched
+    pub dry_run: bool,
+    // the playlist the tracks went into; None for dry runs
+    pub playlist: Option<u64>,
+    pub name: String,
+    // how many tracks the data listed, and how many of them were found
+    pub tracks: usize,
+    pub imported: usize,
+    pub failed: Vec<ImportFailure>,
+}
+
+/// A playlist written out as asked; sent only to the client which asked.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlaylistExport {
+    pub when: u64,
+    pub playlist: u64,
+    pub format: PlaylistFormat,
+    pub data: String,
+}
+
+/// A user's playlists; sent only to their own client, when it asks and
+/// whenever they change.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Playlists {
+    pub when: u64,
+    // the playlist tracks are played from once the DJ queue runs dry
+    pub active: Option<u64>,
+    pub playlists: Vec<Playlist>,
 }
 
 #[derive(Debug)]
@@ -83,6 +769,11 @@ pub enum PlaybackMessage {
     EnqueueItem(EnqueueItem),
     PlayItem(PlayItem),
     Skip(Skip),
+    Booth(Booth),
+    Votes(Votes),
+    Played(PlayRecord),
+    DequeueItem(DequeueItem),
+    MoveItem(MoveItem),
 }
 
 impl serde::Serialize for PlaybackMessage {
@@ -97,6 +788,11 @@ impl serde::Serialize for PlaybackMessage {
             PM::EnqueueItem(ref body) => (PMF::EnqueueItem, body).serialize(serializer),
             PM::PlayItem(ref body) => (PMF::PlayItem, body).serialize(serializer),
             PM::Skip(ref body) => (PMF::Skip, body).serialize(serializer),
+            PM::Booth(ref body) => (PMF::Booth, body).serialize(serializer),
+            PM::Votes(ref body) => (PMF::Votes, body).serialize(serializer),
+            PM::Played(ref body) => (PMF::Played, body).serialize(serializer),
+            PM::DequeueItem(ref body) => (PMF::DequeueItem, body).serialize(serializer),
+            PM::MoveItem(ref body) => (PMF::MoveItem, body).serialize(serializer),
         }
     }
 }
@@ -137,6 +833,26 @@ impl serde::de::Visitor for PlaybackMessageVisitor {
                 let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
                 PM::Skip(body)
             },
+            PMF::Booth => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                PM::Booth(body)
+            },
+            PMF::Votes => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                PM::Votes(body)
+            },
+            PMF::Played => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                PM::Played(body)
+            },
+            PMF::DequeueItem => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                PM::DequeueItem(body)
+            },
+            PMF::MoveItem => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                PM::MoveItem(body)
+            },
         };
 
         try!(visitor.end());
@@ -149,6 +865,11 @@ enum PlaybackMessageField {
     EnqueueItem,
     PlayItem,
     Skip,
+    Booth,
+    Votes,
+    Played,
+    DequeueItem,
+    MoveItem,
 }
 
 impl serde::Serialize for PlaybackMessageField {
@@ -191,6 +912,11 @@ impl PlaybackMessageField {
             field::ENQUEUE_ITEM => Ok(PMF::EnqueueItem),
             field::PLAY_ITEM => Ok(PMF::PlayItem),
             field::SKIP => Ok(PMF::Skip),
+            field::BOOTH => Ok(PMF::Booth),
+            field::VOTES => Ok(PMF::Votes),
+            field::PLAYED => Ok(PMF::Played),
+            field::DEQUEUE_ITEM => Ok(PMF::DequeueItem),
+            field::MOVE_ITEM => Ok(PMF::MoveItem),
             _ => Err(()),
         }
     }
@@ -202,6 +928,11 @@ impl PlaybackMessageField {
             PMF::EnqueueItem => field::ENQUEUE_ITEM,
             PMF::PlayItem => field::PLAY_ITEM,
             PMF::Skip => field::SKIP,
+            PMF::Booth => field::BOOTH,
+            PMF::Votes => field::VOTES,
+            PMF::Played => field::PLAYED,
+            PMF::DequeueItem => field::DEQUEUE_ITEM,
+            PMF::MoveItem => field::MOVE_ITEM,
         }
     }
 }
@@ -214,6 +945,17 @@ pub enum EgressMessage {
     StatusMessage(StatusMessage),
     UserMessage(UserMessage),
     PlaybackMessage(PlaybackMessage),
+    Welcome(Welcome),
+    Error(Error),
+    Ack(Ack),
+    Nack(Nack),
+    NickChange(NickChange),
+    RoleChange(RoleChange),
+    Moderation(Moderation),
+    History(History),
+    Playlists(Playlists),
+    ImportReport(ImportReport),
+    PlaylistExport(PlaylistExport),
 }
 
 impl serde::Serialize for EgressMessage {
@@ -231,6 +973,17 @@ impl serde::Serialize for EgressMessage {
             EM::StatusMessage(ref body) => (EMF::StatusMessage, body).serialize(serializer),
             EM::UserMessage(ref body) => (EMF::UserMessage, body).serialize(serializer),
             EM::PlaybackMessage(ref body) => (EMF::PlaybackMessage, body).serialize(serializer),
+            EM::Welcome(ref body) => (EMF::Welcome, body).serialize(serializer),
+            EM::Error(ref body) => (EMF::Error, body).serialize(serializer),
+            EM::Ack(ref body) => (EMF::Ack, body).serialize(serializer),
+            EM::Nack(ref body) => (EMF::Nack, body).serialize(serializer),
+            EM::NickChange(ref body) => (EMF::NickChange, body).serialize(serializer),
+            EM::RoleChange(ref body) => (EMF::RoleChange, body).serialize(serializer),
+            EM::Moderation(ref body) => (EMF::Moderation, body).serialize(serializer),
+            EM::History(ref body) => (EMF::History, body).serialize(serializer),
+            EM::Playlists(ref body) => (EMF::Playlists, body).serialize(serializer),
+            EM::ImportReport(ref body) => (EMF::ImportReport, body).serialize(serializer),
+            EM::PlaylistExport(ref body) => (EMF::PlaylistExport, body).serialize(serializer),
         }
     }
 }
@@ -283,6 +1036,50 @@ impl serde::de::Visitor for EgressMessageVisitor {
                 let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
                 EM::PlaybackMessage(body)
             },
+            EMF::Welcome => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                EM::Welcome(body)
+            },
+            EMF::Error => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                EM::Error(body)
+            },
+            EMF::Ack => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                EM::Ack(body)
+            },
+            EMF::Nack => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                EM::Nack(body)
+            },
+            EMF::NickChange => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                EM::NickChange(body)
+            },
+            EMF::RoleChange => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                EM::RoleChange(body)
+            },
+            EMF::Moderation => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                EM::Moderation(body)
+            },
+            EMF::History => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                EM::History(body)
+            },
+            EMF::Playlists => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                EM::Playlists(body)
+            },
+            EMF::ImportReport => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                EM::ImportReport(body)
+            },
+            EMF::PlaylistExport => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                EM::PlaylistExport(body)
+            },
         };
 
         try!(visitor.end());
@@ -298,6 +1095,17 @@ enum EgressMessageField {
     StatusMessage,
     UserMessage,
     PlaybackMessage,
+    Welcome,
+    Error,
+    Ack,
+    Nack,
+    NickChange,
+    RoleChange,
+    Moderation,
+    History,
+    Playlists,
+    ImportReport,
+    PlaylistExport,
 }
 
 impl EgressMessageField {
@@ -311,6 +1119,17 @@ impl EgressMessageField {
             field::STATUS_MESSAGE => Ok(EMF::StatusMessage),
             field::USER_MESSAGE => Ok(EMF::UserMessage),
             field::PLAYBACK_MESSAGE => Ok(EMF::PlaybackMessage),
+            field::WELCOME => Ok(EMF::Welcome),
+            field::ERROR => Ok(EMF::Error),
+            field::ACK => Ok(EMF::Ack),
+            field::NACK => Ok(EMF::Nack),
+            field::NICK_CHANGE => Ok(EMF::NickChange),
+            field::ROLE_CHANGE => Ok(EMF::RoleChange),
+            field::MODERATION => Ok(EMF::Moderation),
+            field::HISTORY => Ok(EMF::History),
+            field::PLAYLISTS => Ok(EMF::Playlists),
+            field::IMPORT_REPORT => Ok(EMF::ImportReport),
+            field::PLAYLIST_EXPORT => Ok(EMF::PlaylistExport),
             _ => Err(()),
         }
     }
@@ -325,6 +1144,17 @@ impl EgressMessageField {
             IMF::StatusMessage => field::STATUS_MESSAGE,
             IMF::UserMessage => field::USER_MESSAGE,
             IMF::PlaybackMessage => field::PLAYBACK_MESSAGE,
+            IMF::Welcome => field::WELCOME,
+            IMF::Error => field::ERROR,
+            IMF::Ack => field::ACK,
+            IMF::Nack => field::NACK,
+            IMF::NickChange => field::NICK_CHANGE,
+            IMF::RoleChange => field::ROLE_CHANGE,
+            IMF::Moderation => field::MODERATION,
+            IMF::History => field::HISTORY,
+            IMF::Playlists => field::PLAYLISTS,
+            IMF::ImportReport => field::IMPORT_REPORT,
+            IMF::PlaylistExport => field::PLAYLIST_EXPORT,
         }
     }
 }
@@ -360,11 +1190,170 @@ impl serde::de::Visitor for EgressMessageFieldVisitor {
     }
 }
 
+/// A time-sync ping. The server answers with a `Clock` echoing `when` as
+/// `origin`, which lets the client estimate both its offset from the server
+/// clock and the round-trip latency, as in NTP.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClockRequest {
+    // the client's clock when it sent the request
+    pub when: u64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RegisterMessage {
     pub nick: String,
 }
 
+/// Changes the nick of a user who has already registered.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NickMessage {
+    pub nick: String,
+}
+
+/// Promotes or demotes another user. Only roles below one's own can be
+/// handed out, and only to users below one's own role.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetRoleMessage {
+    pub uid: super::UserId,
+    pub role: Role,
+}
+
+/// Removes a user from the room. They may come straight back.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KickMessage {
+    pub uid: super::UserId,
+    pub reason: Option<String>,
+}
+
+/// Stops a user from chatting for a while. Authenticated users are also
+/// muted by nick, and `ip` mutes the address they are connecting from too.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MuteMessage {
+    pub uid: super::UserId,
+    // in milliseconds
+    pub duration: u64,
+    pub ip: Option<bool>,
+    pub reason: Option<String>,
+}
+
+/// Removes a user from the room and keeps them out, for `duration`
+/// milliseconds or for good. Authenticated users are also banned by nick,
+/// and `ip` bans the address they are connecting from too.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BanMessage {
+    pub uid: super::UserId,
+    pub duration: Option<u64>,
+    pub ip: Option<bool>,
+    pub reason: Option<String>,
+}
+
+/// A request which only names the user it acts on.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TargetMessage {
+    pub uid: super::UserId,
+}
+
+/// Adds a track to the end of one's own DJ queue.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EnqueueMessage {
+    pub media: MediaRef,
+    // what the client knows about the track; only used for providers the
+    // server is configured to take the client's word for
+    pub info: Option<MediaInfo>,
+}
+
+/// Takes a track out of one's own DJ queue.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DequeueMessage {
+    pub eid: u64,
+}
+
+/// Moves a track within one's own DJ queue, 0 being next up.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReorderMessage {
+    pub eid: u64,
+    pub position: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreatePlaylistMessage {
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RenamePlaylistMessage {
+    pub playlist: u64,
+    pub name: String,
+}
+
+/// Names one of the user's own playlists, for requests which need nothing
+/// else.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlaylistMessage {
+    pub playlist: u64,
+}
+
+/// Adds a track to the end of a playlist.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlaylistAddMessage {
+    pub playlist: u64,
+    pub media: MediaRef,
+    // as in `EnqueueMessage`
+    pub info: Option<MediaInfo>,
+}
+
+/// Takes the track at `index` out of a playlist.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlaylistRemoveMessage {
+    pub playlist: u64,
+    pub index: usize,
+}
+
+/// Moves the track at `from` in a playlist to `to`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlaylistMoveMessage {
+    pub playlist: u64,
+    pub from: usize,
+    pub to: usize,
+}
+
+/// Makes a playlist out of `data`, written in `format`, looking up every
+/// track in it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportPlaylistMessage {
+    pub format: PlaylistFormat,
+    pub data: String,
+    // defaults to the name in the data, if it has one
+    pub name: Option<String>,
+    // only report what would be imported
+    pub dry_run: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportPlaylistMessage {
+    pub playlist: u64,
+    pub format: PlaylistFormat,
+}
+
+/// Picks the playlist to DJ from, or none.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ActivatePlaylistMessage {
+    pub playlist: Option<u64>,
+}
+
+/// Votes on the current play. Voting again replaces the earlier vote.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VoteMessage {
+    pub vote: Vote,
+}
+
+/// Registers using a bearer token rather than a bare nick; the nick is
+/// whichever one the token was issued to.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuthenticateMessage {
+    pub token: String,
+}
+
 #[derive(Debug)]
 pub enum IngressMessage {
     Register(RegisterMessage),
@@ -374,6 +1363,154 @@ pub enum IngressMessage {
     DjQueue,
     DjUnqueue,
     Message(String),
+    Clock(ClockRequest),
+    Authenticate(AuthenticateMessage),
+    Nick(NickMessage),
+    SetRole(SetRoleMessage),
+    Kick(KickMessage),
+    Mute(MuteMessage),
+    Unmute(TargetMessage),
+    Ban(BanMessage),
+    Unban(TargetMessage),
+    Vote(VoteMessage),
+    Grab,
+    History,
+    Enqueue(EnqueueMessage),
+    Dequeue(DequeueMessage),
+    Reorder(ReorderMessage),
+    Playlists,
+    PlaylistCreate(CreatePlaylistMessage),
+    PlaylistRename(RenamePlaylistMessage),
+    PlaylistDelete(PlaylistMessage),
+    PlaylistAdd(PlaylistAddMessage),
+    PlaylistRemove(PlaylistRemoveMessage),
+    PlaylistMove(PlaylistMoveMessage),
+    PlaylistShuffle(PlaylistMessage),
+    PlaylistActivate(ActivatePlaylistMessage),
+    PlaylistImport(ImportPlaylistMessage),
+    PlaylistExport(ExportPlaylistMessage),
+}
+
+/// A request as it arrives from a client. Requests may be sent bare, as in
+/// `["dj_queue"]`, or wrapped along with an id of the client's choosing, as in
+/// `{"id": 7, "request": ["dj_queue"]}`, in which case the server answers
+/// with an `Ack` or `Nack` carrying the same id.
+#[derive(Debug)]
+pub struct IngressFrame {
+    pub id: Option<u64>,
+    pub request: IngressMessage,
+}
+
+#[derive(Serialize)]
+struct TaggedFrame<'a> {
+    id: u64,
+    request: &'a IngressMessage,
+}
+
+impl serde::Serialize for IngressFrame {
+    #[inline]
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: serde::Serializer,
+    {
+        use serde::Serialize;
+        match self.id {
+            Some(id) => TaggedFrame { id: id, request: &self.request }.serialize(serializer),
+            None => self.request.serialize(serializer),
+        }
+    }
+}
+
+impl serde::Deserialize for IngressFrame {
+    #[inline]
+    fn deserialize<D>(deserializer: &mut D) -> Result<IngressFrame, D::Error>
+        where D: serde::Deserializer,
+    {
+        deserializer.visit(IngressFrameVisitor)
+    }
+}
+
+struct IngressFrameVisitor;
+
+impl serde::de::Visitor for IngressFrameVisitor {
+    type Value = IngressFrame;
+
+    fn visit_seq<V>(&mut self, visitor: V) -> Result<IngressFrame, V::Error>
+        where V: serde::de::SeqVisitor,
+    {
+        use serde::de::Visitor;
+        let request = try!(IngressMessageVisitor.visit_seq(visitor));
+        Ok(IngressFrame { id: None, request: request })
+    }
+
+    fn visit_map<V>(&mut self, mut visitor: V) -> Result<IngressFrame, V::Error>
+        where V: serde::de::MapVisitor,
+    {
+        use serde::de::Error;
+
+        let mut id = None;
+        let mut request = None;
+        while let Some(key) = try!(visitor.visit_key::<String>()) {
+            match &key[..] {
+                "id" => id = Some(try!(visitor.visit_value())),
+                "request" => request = Some(try!(visitor.visit_value())),
+                _ => return Err(V::Error::unknown_field(&key)),
+            }
+        }
+        let request = match request {
+            Some(request) => request,
+            None => try!(visitor.missing_field("request")),
+        };
+
+        try!(visitor.end());
+        Ok(IngressFrame { id: id, request: request })
+    }
+}
+
+impl IngressMessage {
+    /// The name of this kind of request on the wire, e.g. "dj_queue".
+    pub fn kind(&self) -> &'static str {
+        self.field().name()
+    }
+
+    fn field(&self) -> IngressMessageField {
+        use self::IngressMessage as IM;
+        use self::IngressMessageField as IMF;
+        match *self {
+            IM::Register(_) => IMF::Register,
+            IM::Disconnect => IMF::Disconnect,
+            IM::Skip => IMF::Skip,
+            IM::Part => IMF::Part,
+            IM::DjQueue => IMF::DjQueue,
+            IM::DjUnqueue => IMF::DjUnqueue,
+            IM::Message(_) => IMF::Message,
+            IM::Clock(_) => IMF::Clock,
+            IM::Authenticate(_) => IMF::Authenticate,
+            IM::Nick(_) => IMF::Nick,
+            IM::SetRole(_) => IMF::SetRole,
+            IM::Kick(_) => IMF::Kick,
+            IM::Mute(_) => IMF::Mute,
+            IM::Unmute(_) => IMF::Unmute,
+            IM::Ban(_) => IMF::Ban,
+            IM::Unban(_) => IMF::Unban,
+            IM::Vote(_) => IMF::Vote,
+            IM::Grab => IMF::Grab,
+            IM::History => IMF::History,
+            IM::Enqueue(_) => IMF::Enqueue,
+            IM::Dequeue(_) => IMF::Dequeue,
+            IM::Reorder(_) => IMF::Reorder,
+            IM::Playlists => IMF::Playlists,
+            IM::PlaylistCreate(_) => IMF::PlaylistCreate,
+            IM::PlaylistRename(_) => IMF::PlaylistRename,
+            IM::PlaylistDelete(_) => IMF::PlaylistDelete,
+            IM::PlaylistAdd(_) => IMF::PlaylistAdd,
+            IM::PlaylistRemove(_) => IMF::PlaylistRemove,
+            IM::PlaylistMove(_) => IMF::PlaylistMove,
+            IM::PlaylistShuffle(_) => IMF::PlaylistShuffle,
+            IM::PlaylistActivate(_) => IMF::PlaylistActivate,
+            IM::PlaylistImport(_) => IMF::PlaylistImport,
+            IM::PlaylistExport(_) => IMF::PlaylistExport,
+        }
+    }
 }
 
 impl serde::Serialize for IngressMessage {
@@ -392,6 +1529,32 @@ impl serde::Serialize for IngressMessage {
             IM::DjQueue => (IMF::DjQueue,).serialize(serializer),
             IM::DjUnqueue => (IMF::DjUnqueue,).serialize(serializer),
             IM::Message(ref body) => (IMF::Message, body).serialize(serializer),
+            IM::Clock(ref body) => (IMF::Clock, body).serialize(serializer),
+            IM::Authenticate(ref body) => (IMF::Authenticate, body).serialize(serializer),
+            IM::Nick(ref body) => (IMF::Nick, body).serialize(serializer),
+            IM::SetRole(ref body) => (IMF::SetRole, body).serialize(serializer),
+            IM::Kick(ref body) => (IMF::Kick, body).serialize(serializer),
+            IM::Mute(ref body) => (IMF::Mute, body).serialize(serializer),
+            IM::Unmute(ref body) => (IMF::Unmute, body).serialize(serializer),
+            IM::Ban(ref body) => (IMF::Ban, body).serialize(serializer),
+            IM::Unban(ref body) => (IMF::Unban, body).serialize(serializer),
+            IM::Vote(ref body) => (IMF::Vote, body).serialize(serializer),
+            IM::Grab => (IMF::Grab,).serialize(serializer),
+            IM::History => (IMF::History,).serialize(serializer),
+            IM::Enqueue(ref body) => (IMF::Enqueue, body).serialize(serializer),
+            IM::Dequeue(ref body) => (IMF::Dequeue, body).serialize(serializer),
+            IM::Reorder(ref body) => (IMF::Reorder, body).serialize(serializer),
+            IM::Playlists => (IMF::Playlists,).serialize(serializer),
+            IM::PlaylistCreate(ref body) => (IMF::PlaylistCreate, body).serialize(serializer),
+            IM::PlaylistRename(ref body) => (IMF::PlaylistRename, body).serialize(serializer),
+            IM::PlaylistDelete(ref body) => (IMF::PlaylistDelete, body).serialize(serializer),
+            IM::PlaylistAdd(ref body) => (IMF::PlaylistAdd, body).serialize(serializer),
+            IM::PlaylistRemove(ref body) => (IMF::PlaylistRemove, body).serialize(serializer),
+            IM::PlaylistMove(ref body) => (IMF::PlaylistMove, body).serialize(serializer),
+            IM::PlaylistShuffle(ref body) => (IMF::PlaylistShuffle, body).serialize(serializer),
+            IM::PlaylistActivate(ref body) => (IMF::PlaylistActivate, body).serialize(serializer),
+            IM::PlaylistImport(ref body) => (IMF::PlaylistImport, body).serialize(serializer),
+            IM::PlaylistExport(ref body) => (IMF::PlaylistExport, body).serialize(serializer),
         }
     }
 }
@@ -433,6 +1596,101 @@ impl serde::de::Visitor for IngressMessageVisitor {
             let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
                 IM::Message(body)
             },
+            IMF::Clock => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                IM::Clock(body)
+            },
+            IMF::Authenticate => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                IM::Authenticate(body)
+            },
+            IMF::Nick => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                IM::Nick(body)
+            },
+            IMF::SetRole => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                IM::SetRole(body)
+            },
+            IMF::Kick => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                IM::Kick(body)
+            },
+            IMF::Mute => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                IM::Mute(body)
+            },
+            IMF::Unmute => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                IM::Unmute(body)
+            },
+            IMF::Ban => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                IM::Ban(body)
+            },
+            IMF::Unban => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                IM::Unban(body)
+            },
+            IMF::Vote => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                IM::Vote(body)
+            },
+            IMF::Grab => IM::Grab,
+            IMF::History => IM::History,
+            IMF::Enqueue => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                IM::Enqueue(body)
+            },
+            IMF::Dequeue => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                IM::Dequeue(body)
+            },
+            IMF::Reorder => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                IM::Reorder(body)
+            },
+            IMF::Playlists => IM::Playlists,
+            IMF::PlaylistCreate => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                IM::PlaylistCreate(body)
+            },
+            IMF::PlaylistRename => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                IM::PlaylistRename(body)
+            },
+            IMF::PlaylistDelete => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                IM::PlaylistDelete(body)
+            },
+            IMF::PlaylistAdd => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                IM::PlaylistAdd(body)
+            },
+            IMF::PlaylistRemove => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                IM::PlaylistRemove(body)
+            },
+            IMF::PlaylistMove => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                IM::PlaylistMove(body)
+            },
+            IMF::PlaylistShuffle => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                IM::PlaylistShuffle(body)
+            },
+            IMF::PlaylistActivate => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                IM::PlaylistActivate(body)
+            },
+            IMF::PlaylistImport => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                IM::PlaylistImport(body)
+            },
+            IMF::PlaylistExport => {
+                let body = try!(try!(visitor.visit()).ok_or(V::Error::syntax("truncated list")));
+                IM::PlaylistExport(body)
+            },
         };
 
         try!(visitor.end());
@@ -449,6 +1707,32 @@ enum IngressMessageField {
     DjQueue,
     DjUnqueue,
     Message,
+    Clock,
+    Authenticate,
+    Nick,
+    SetRole,
+    Kick,
+    Mute,
+    Unmute,
+    Ban,
+    Unban,
+    Vote,
+    Grab,
+    History,
+    Enqueue,
+    Dequeue,
+    Reorder,
+    Playlists,
+    PlaylistCreate,
+    PlaylistRename,
+    PlaylistDelete,
+    PlaylistAdd,
+    PlaylistRemove,
+    PlaylistMove,
+    PlaylistShuffle,
+    PlaylistActivate,
+    PlaylistImport,
+    PlaylistExport,
 }
 
 impl IngressMessageField {
@@ -463,6 +1747,32 @@ impl IngressMessageField {
             field::DJ_QUEUE => Ok(IMF::DjQueue),
             field::DJ_UNQUEUE => Ok(IMF::DjUnqueue),
             field::MESSAGE => Ok(IMF::Message),
+            field::CLOCK => Ok(IMF::Clock),
+            field::AUTHENTICATE => Ok(IMF::Authenticate),
+            field::NICK => Ok(IMF::Nick),
+            field::SET_ROLE => Ok(IMF::SetRole),
+            field::KICK => Ok(IMF::Kick),
+            field::MUTE => Ok(IMF::Mute),
+            field::UNMUTE => Ok(IMF::Unmute),
+            field::BAN => Ok(IMF::Ban),
+            field::UNBAN => Ok(IMF::Unban),
+            field::VOTE => Ok(IMF::Vote),
+            field::GRAB => Ok(IMF::Grab),
+            field::HISTORY => Ok(IMF::History),
+            field::ENQUEUE => Ok(IMF::Enqueue),
+            field::DEQUEUE => Ok(IMF::Dequeue),
+            field::REORDER => Ok(IMF::Reorder),
+            field::PLAYLISTS => Ok(IMF::Playlists),
+            field::PLAYLIST_CREATE => Ok(IMF::PlaylistCreate),
+            field::PLAYLIST_RENAME => Ok(IMF::PlaylistRename),
+            field::PLAYLIST_DELETE => Ok(IMF::PlaylistDelete),
+            field::PLAYLIST_ADD => Ok(IMF::PlaylistAdd),
+            field::PLAYLIST_REMOVE => Ok(IMF::PlaylistRemove),
+            field::PLAYLIST_MOVE => Ok(IMF::PlaylistMove),
+            field::PLAYLIST_SHUFFLE => Ok(IMF::PlaylistShuffle),
+            field::PLAYLIST_ACTIVATE => Ok(IMF::PlaylistActivate),
+            field::PLAYLIST_IMPORT => Ok(IMF::PlaylistImport),
+            field::PLAYLIST_EXPORT => Ok(IMF::PlaylistExport),
             _ => Err(()),
         }
     }
@@ -478,6 +1788,32 @@ impl IngressMessageField {
             IMF::DjQueue => field::DJ_QUEUE,
             IMF::DjUnqueue => field::DJ_UNQUEUE,
             IMF::Message => field::MESSAGE,
+            IMF::Clock => field::CLOCK,
+            IMF::Authenticate => field::AUTHENTICATE,
+            IMF::Nick => field::NICK,
+            IMF::SetRole => field::SET_ROLE,
+            IMF::Kick => field::KICK,
+            IMF::Mute => field::MUTE,
+            IMF::Unmute => field::UNMUTE,
+            IMF::Ban => field::BAN,
+            IMF::Unban => field::UNBAN,
+            IMF::Vote => field::VOTE,
+            IMF::Grab => field::GRAB,
+            IMF::History => field::HISTORY,
+            IMF::Enqueue => field::ENQUEUE,
+            IMF::Dequeue => field::DEQUEUE,
+            IMF::Reorder => field::REORDER,
+            IMF::Playlists => field::PLAYLISTS,
+            IMF::PlaylistCreate => field::PLAYLIST_CREATE,
+            IMF::PlaylistRename => field::PLAYLIST_RENAME,
+            IMF::PlaylistDelete => field::PLAYLIST_DELETE,
+            IMF::PlaylistAdd => field::PLAYLIST_ADD,
+            IMF::PlaylistRemove => field::PLAYLIST_REMOVE,
+            IMF::PlaylistMove => field::PLAYLIST_MOVE,
+            IMF::PlaylistShuffle => field::PLAYLIST_SHUFFLE,
+            IMF::PlaylistActivate => field::PLAYLIST_ACTIVATE,
+            IMF::PlaylistImport => field::PLAYLIST_IMPORT,
+            IMF::PlaylistExport => field::PLAYLIST_EXPORT,
         }
     }
 }
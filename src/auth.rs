@@ -0,0 +1,272 @@
+use std::fmt;
+use std::io::Read;
+use std::sync::Arc;
+use std::time::Duration;
+use crypto::hmac::Hmac;
+use crypto::mac::{Mac, MacResult};
+use crypto::sha2::Sha256;
+use hyper;
+use hyper::header::{Authorization, Basic, ContentType};
+use rustc_serialize::base64::{FromBase64, ToBase64, URL_SAFE};
+use serde_json;
+use time;
+
+use config::AuthConfig;
+use form;
+
+// how long to wait on the introspection endpoint before giving up on it, in
+// milliseconds
+const TIMEOUT: u64 = 10_000;
+
+/// Builds the authenticator described by the config, if any.
+pub fn from_config(config: &AuthConfig) -> Option<Arc<Authenticator>> {
+    match *config {
+        AuthConfig::Disabled => None,
+        AuthConfig::Hmac { ref secret } => {
+            Some(Arc::new(HmacVerifier::new(secret.as_bytes())))
+        },
+        AuthConfig::Introspection { ref url, ref client_id, ref client_secret } => {
+            Some(Arc::new(Introspection::new(url, client_id.clone(), client_secret.clone())))
+        },
+    }
+}
+
+/// Who a token was issued to.
+#[derive(Clone, Debug)]
+pub struct Identity {
+    pub nick: String,
+    pub token: String,
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    // the token is malformed, forged, expired or revoked
+    Rejected(String),
+    // we couldn't find out whether the token is any good
+    Unavailable(String),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AuthError::Rejected(ref msg) => write!(f, "token rejected: {}", msg),
+            AuthError::Unavailable(ref msg) => write!(f, "unable to check token: {}", msg),
+        }
+    }
+}
+
+/// Turns bearer tokens into identities. Implementations may block, so they
+/// should only be called from client threads, never from a room.
+pub trait Authenticator: Send + Sync {
+    fn authenticate(&self, token: &str) -> Result<Identity, AuthError>;
+}
+
+/// Verifies tokens we signed ourselves with a shared secret. A token is
+/// `base64(nick).expiry.base64(hmac-sha256(secret, "base64(nick).expiry"))`,
+/// where expiry is in seconds since the unix epoch.
+pub struct HmacVerifier {
+    secret: Vec<u8>,
+}
+
+impl HmacVerifier {
+    pub fn new(secret: &[u8]) -> HmacVerifier {
+        HmacVerifier { secret: secret.to_vec() }
+    }
+
+    fn sign(&self, payload: &str) -> MacResult {
+        let mut hmac = Hmac::new(Sha256::new(), &self.secret);
+        hmac.input(payload.as_bytes());
+        hmac.result()
+    }
+
+    /// Makes a token for `nick` which is good until `expires`.
+    pub fn issue(&self, nick: &str, expires: u64) -> String {
+        let payload = format!("{}.{}", nick.as_bytes().to_base64(URL_SAFE), expires);
+        let signature = self.sign(&payload).code().to_base64(URL_SAFE);
+        format!("{}.{}", payload, signature)
+    }
+}
+
+impl Authenticator for HmacVerifier {
+    fn authenticate(&self, token: &str) -> Result<Identity, AuthError> {
+        let malformed = || AuthError::Rejected("malformed token".to_string());
+
+        let split = try!(token.rfind('.').ok_or(malformed()));
+        let (payload, signature) = (&token[..split], &token[split + 1..]);
+        let signature = try!(signature.from_base64().map_err(|_| malformed()));
+        // MacResult compares in constant time
+        if self.sign(payload) != MacResult::new(&signature) {
+            return Err(AuthError::Rejected("bad signature".to_string()));
+        }
+
+        let split = try!(payload.find('.').ok_or(malformed()));
+        let (nick, expires) = (&payload[..split], &payload[split + 1..]);
+        let expires: u64 = try!(expires.parse().map_err(|_| malformed()));
+        if expires < time::get_time().sec as u64 {
+            return Err(AuthError::Rejected("token has expired".to_string()));
+        }
+        let nick = try!(nick.from_base64().map_err(|_| malformed()));
+        let nick = try!(String::from_utf8(nick).map_err(|_| malformed()));
+
+        Ok(Identity {
+            nick: nick,
+            token: token.to_string(),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct IntrospectionResponse {
+    active: bool,
+    username: Option<String>,
+    sub: Option<String>,
+}
+
+/// Asks an OAuth2 authorization server about tokens it issued, using token
+/// introspection (RFC 7662).
+pub struct Introspection {
+    url: String,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+}
+
+impl Introspection {
+    pub fn new(url: &str, client_id: Option<String>, client_secret: Option<String>) -> Introspection {
+        Introspection {
+            url: url.to_string(),
+            client_id: client_id,
+            client_secret: client_secret,
+        }
+    }
+}
+
+impl Authenticator for Introspection {
+    fn authenticate(&self, token: &str) -> Result<Identity, AuthError> {
+        let unavailable = |err: &fmt::Display| AuthError::Unavailable(err.to_string());
+
+        let mut client = hyper::Client::new();
+        client.set_read_timeout(Some(Duration::from_millis(TIMEOUT)));
+        client.set_write_timeout(Some(Duration::from_millis(TIMEOUT)));
+        let body = format!("token={}&token_type_hint=access_token", form::encode(token));
+        let mut request = client.post(&self.url[..])
+            .header(ContentType::form_url_encoded())
+            .body(&body[..]);
+        if let Some(ref client_id) = self.client_id {
+            request = request.header(Authorization(Basic {
+                username: client_id.clone(),
+                password: self.client_secret.clone(),
+            }));
+        }
+        let mut response = try!(request.send().map_err(|e| unavailable(&e)));
+        if response.status != hyper::Ok {
+            return Err(AuthError::Unavailable(format!("introspection endpoint said {}", response.status)));
+        }
+        let mut text = String::new();
+        try!(response.read_to_string(&mut text).map_err(|e| unavailable(&e)));
+        let info: IntrospectionResponse = try!(serde_json::from_str(&text).map_err(|e| unavailable(&e)));
+
+        if !info.active {
+            return Err(AuthError::Rejected("token is not active".to_string()));
+        }
+        let nick = match info.username.or(info.sub) {
+            Some(nick) => nick,
+            None => return Err(AuthError::Unavailable("token has no username".to_string())),
+        };
+        Ok(Identity {
+            nick: nick,
+            token: token.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::net::SocketAddr;
+    use hyper;
+    use hyper::server::{Request, Response, Server};
+    use time;
+
+    use super::*;
+
+    fn now() -> u64 {
+        time::get_time().sec as u64
+    }
+
+    fn rejected(result: Result<Identity, AuthError>) -> bool {
+        match result {
+            Err(AuthError::Rejected(_)) => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn hmac_tokens_name_who_they_were_issued_to() {
+        let verifier = HmacVerifier::new(b"secret");
+        let token = verifier.issue("alice", now() + 3600);
+        let identity = verifier.authenticate(&token).unwrap();
+        assert_eq!(identity.nick, "alice");
+        assert_eq!(identity.token, token);
+    }
+
+    #[test]
+    fn hmac_tokens_expire() {
+        let verifier = HmacVerifier::new(b"secret");
+        assert!(rejected(verifier.authenticate(&verifier.issue("alice", now() - 1))));
+    }
+
+    #[test]
+    fn forged_hmac_tokens_are_rejected() {
+        let verifier = HmacVerifier::new(b"secret");
+        let forged = HmacVerifier::new(b"guess").issue("alice", now() + 3600);
+        assert!(rejected(verifier.authenticate(&forged)));
+
+        // someone else's signature on a payload naming alice
+        let token = verifier.issue("mallory", now() + 3600);
+        let signature = &token[token.rfind('.').unwrap()..];
+        let payload = verifier.issue("alice", now() + 3600);
+        let payload = &payload[..payload.rfind('.').unwrap()];
+        assert!(rejected(verifier.authenticate(&format!("{}{}", payload, signature))));
+
+        assert!(rejected(verifier.authenticate("not a token")));
+    }
+
+    /// Serves token introspection on a local port, treating "good" as the
+    /// only active token.
+    fn introspection_server(status: hyper::status::StatusCode) -> SocketAddr {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let mut listening = server.handle(move |mut req: Request, mut res: Response| {
+            let mut body = String::new();
+            req.read_to_string(&mut body).unwrap();
+            let reply = if body.starts_with("token=good&") {
+                r#"{"active": true, "username": "alice"}"#
+            } else {
+                r#"{"active": false}"#
+            };
+            *res.status_mut() = status;
+            res.send(reply.as_bytes()).unwrap();
+        }).unwrap();
+        // this only lets go of the server's thread, which goes on answering
+        // until the tests are done
+        listening.close().unwrap();
+        listening.socket
+    }
+
+    #[test]
+    fn introspection_asks_the_server_about_tokens() {
+        let addr = introspection_server(hyper::Ok);
+        let introspection = Introspection::new(&format!("http://{}/introspect", addr), None, None);
+        assert_eq!(introspection.authenticate("good").unwrap().nick, "alice");
+        assert!(rejected(introspection.authenticate("bad")));
+    }
+
+    #[test]
+    fn introspection_errors_leave_tokens_unchecked() {
+        let addr = introspection_server(hyper::status::StatusCode::InternalServerError);
+        let introspection = Introspection::new(&format!("http://{}/introspect", addr), None, None);
+        match introspection.authenticate("good") {
+            Err(AuthError::Unavailable(_)) => (),
+            other => panic!("expected Unavailable, got {:?}", other),
+        }
+    }
+}
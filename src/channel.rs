@@ -0,0 +1,1635 @@
+use std::cmp;
+use std::mem;
+use std::net::IpAddr;
+use std::thread;
+use std::sync::{mpsc, Arc};
+use std::collections::{HashMap, HashSet, BTreeMap, VecDeque};
+use serde_json;
+
+use api;
+use auth::Identity;
+use clock;
+use config::RoomConfig;
+use media::{self, MediaError, Providers};
+use moderation::{Sanction, Sanctions};
+use nicks::{self, NickError, NickIndex};
+use outbox::Outbox;
+use playlists::{Import, PlaylistError, Store};
+use policy::{self, Decision, Policy};
+use ratelimit::Limiter;
+use session::Session;
+use timer::Timer;
+use super::{User, UserId};
+
+/// A client whose handshake has been accepted.
+pub struct NewClient {
+    // the user it belongs to
+    pub uid: UserId,
+    // the serial of its connection; see `Session`
+    pub conn: u64,
+    pub ip: IpAddr,
+    pub outbox: Outbox,
+    // who the client proved to be, if anyone
+    pub identity: Option<Identity>,
+}
+
+pub enum ChanMessage {
+    Introduce(NewClient),
+    // a client connecting from the given address, authenticated as the
+    // given nick, if any. Answered with the reason it's banned, if it is,
+    // before the handshake is accepted.
+    Admit(IpAddr, Option<String>, mpsc::Sender<Result<(), String>>),
+    // a client reconnecting with a resume token on the connection with the
+    // given serial. Answered with the user it belonged to, if it's still
+    // valid, before the connection is introduced.
+    Resume(String, u64, mpsc::Sender<Option<UserId>>),
+    Status(String),
+    Message(UserId, api::IngressFrame),
+    // the client sent a frame we couldn't make sense of; carries the
+    // request id and kind, if they could be made out
+    Invalid(UserId, Option<u64>, Rejection, Option<String>),
+    // the connection with the given serial has closed
+    Disconnected(UserId, u64),
+    // the grace period for the user's connection with the given serial has
+    // run out
+    Expire(UserId, u64),
+    // the outcome of checking the token in an `authenticate` request
+    Authenticated(UserId, Option<u64>, Result<Identity, Rejection>),
+    // the play with the given serial has reached the end of its duration
+    TrackEnd(u64),
+    // the outcome of looking up the track in a request of the given id
+    Resolved(UserId, Option<u64>, Lookup, api::MediaRef, Result<api::MediaInfo, MediaError>),
+    // the outcome of a `playlist_import` request of the given id
+    Imported(UserId, Option<u64>, Result<api::ImportReport, PlaylistError>),
+    // time to close the room, if it has been empty long enough
+    Reap,
+}
+
+/// How a room started on demand closes once it's been empty for a while.
+pub struct Reaper {
+    // how long it must have been empty, in milliseconds
+    pub after: u64,
+    // takes the room out of those running, unless a client is on its way
+    // in; returns whether it did
+    pub close: Box<Fn() -> bool + Send>,
+}
+
+/// Why a track was looked up.
+pub enum Lookup {
+    Enqueue,
+    // adding it to the playlist with the given id
+    PlaylistAdd(u64),
+}
+
+impl Lookup {
+    /// The kind of request which asked for the lookup.
+    fn kind(&self) -> &'static str {
+        match *self {
+            Lookup::Enqueue => "enqueue",
+            Lookup::PlaylistAdd(_) => "playlist_add",
+        }
+    }
+}
+
+/// Why a request was refused; reported back to the client as an `Error`.
+pub struct Rejection {
+    code: api::ErrorCode,
+    message: String,
+}
+
+impl Rejection {
+    pub fn new(code: api::ErrorCode, message: &str) -> Rejection {
+        Rejection {
+            code: code,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<NickError> for Rejection {
+    fn from(err: NickError) -> Rejection {
+        match err {
+            NickError::Invalid(msg) => Rejection::new(api::ErrorCode::Invalid, msg),
+            NickError::Taken => Rejection::new(api::ErrorCode::Conflict, "nick is taken"),
+        }
+    }
+}
+
+impl From<MediaError> for Rejection {
+    fn from(err: MediaError) -> Rejection {
+        match err {
+            MediaError::Invalid(msg) => Rejection::new(api::ErrorCode::Invalid, &msg),
+            MediaError::NotFound(msg) => Rejection::new(api::ErrorCode::NotFound, &msg),
+            MediaError::Unplayable(msg) => Rejection::new(api::ErrorCode::Invalid, &msg),
+            MediaError::Unavailable(msg) => Rejection::new(api::ErrorCode::Unavailable, &msg),
+        }
+    }
+}
+
+impl From<PlaylistError> for Rejection {
+    fn from(err: PlaylistError) -> Rejection {
+        match err {
+            PlaylistError::Invalid(msg) => Rejection::new(api::ErrorCode::Invalid, &msg),
+            PlaylistError::NotFound(msg) => Rejection::new(api::ErrorCode::NotFound, &msg),
+            PlaylistError::LimitReached(msg) => Rejection::new(api::ErrorCode::LimitReached, &msg),
+        }
+    }
+}
+
+struct NowPlaying {
+    // distinguishes this play from earlier plays of the same item, so
+    // timers belonging to skipped tracks can be ignored.
+    serial: u64,
+    item: api::PlayItem,
+    votes: HashMap<UserId, api::Vote>,
+    grabs: HashSet<UserId>,
+}
+
+impl NowPlaying {
+    fn tally(&self, when: u64, voter: Option<UserId>) -> api::Votes {
+        let count = |vote: api::Vote| self.votes.values().filter(|&&v| v == vote).count() as u32;
+        api::Votes {
+            when: when,
+            uid: voter,
+            woots: count(api::Vote::Woot),
+            mehs: count(api::Vote::Meh),
+            grabs: self.grabs.len() as u32,
+        }
+    }
+}
+
+// what the session playlists of `uid` are kept under
+fn session_key(uid: UserId) -> String {
+    uid.0.to_string()
+}
+
+struct Channel {
+    nicks: NickIndex,
+    policy: Box<Policy>,
+    // roles of authenticated users, by nick_key, including those handed out
+    // since the room started
+    staff: HashMap<String, api::Role>,
+    bans: Sanctions,
+    mutes: Sanctions,
+    // keyed by `limiter_key`, and kept until they've been idle a while
+    limiters: HashMap<String, Limiter>,
+    users: HashMap<UserId, User>,
+    sessions: HashMap<UserId, Session>,
+    resume_tokens: HashMap<String, UserId>,
+    clients: BTreeMap<UserId, Outbox>,
+    dj_queue: VecDeque<UserId>,
+    // the upcoming tracks of each DJ, played in turn from the dj_queue
+    dj_tracks: HashMap<UserId, VecDeque<api::PlayItem>>,
+    now_playing: Option<NowPlaying>,
+    // the last `history_len` plays, oldest first
+    history: VecDeque<api::PlayRecord>,
+    play_serial: u64,
+    // the last eid handed out to a DJ queue entry
+    entry_serial: u64,
+    timebase: clock::Timebase,
+    timer: Timer,
+    providers: Arc<Providers>,
+    playlists: Arc<Store>,
+    // the playlists of users who registered without authenticating, by uid,
+    // which last only as long as they're here
+    session_playlists: Arc<Store>,
+    config: RoomConfig,
+    // None for rooms which run for as long as the server does
+    reaper: Option<Reaper>,
+    // when the last user left, if nobody has come since
+    empty_since: Option<u64>,
+    tx: mpsc::Sender<ChanMessage>,
+    rx: mpsc::Receiver<ChanMessage>,
+}
+
+impl Channel {
+    pub fn new(timebase: clock::Timebase,
+               timer: Timer,
+               providers: Arc<Providers>,
+               playlists: Arc<Store>,
+               config: RoomConfig,
+               reaper: Option<Reaper>,
+               tx: mpsc::Sender<ChanMessage>,
+               rx: mpsc::Receiver<ChanMessage>) -> Channel {
+        let staff = config.roles.iter()
+            .map(|(nick, &role)| (nicks::nick_key(nick), role))
+            .collect();
+        Channel {
+            nicks: NickIndex::new(),
+            policy: Box::new(config.permissions.clone()),
+            staff: staff,
+            bans: Sanctions::new(),
+            mutes: Sanctions::new(),
+            limiters: HashMap::new(),
+            users: HashMap::new(),
+            sessions: HashMap::new(),
+            resume_tokens: HashMap::new(),
+            clients: BTreeMap::new(),
+            dj_queue: VecDeque::new(),
+            dj_tracks: HashMap::new(),
+            now_playing: None,
+            history: VecDeque::new(),
+            play_serial: 0,
+            entry_serial: 0,
+            timebase: timebase,
+            timer: timer,
+            providers: providers,
+            session_playlists: Arc::new(playlists.scratch()),
+            playlists: playlists,
+            config: config,
+            reaper: reaper,
+            empty_since: None,
+            tx: tx,
+            rx: rx,
+        }
+    }
+
+    fn now(&self) -> u64 {
+        self.timebase.now()
+    }
+
+    pub fn dispatch_msg(&mut self, msg: api::EgressMessage) {
+        let message = Arc::new(serde_json::to_string(&msg).unwrap());
+        let mut dead_uid = Vec::new();
+
+        for (uid, client) in self.clients.iter() {
+            if let Err(err) = client.push(message.clone()) {
+                info!("dropping client {:?}: {:?}", uid, err);
+                dead_uid.push(*uid);
+            }
+        }
+
+        // their readers will report the disconnect once the socket closes
+        for uid in dead_uid.into_iter() {
+            self.clients.remove(&uid);
+        }
+    }
+
+    pub fn send_to(&mut self, uid: UserId, msg: api::EgressMessage) {
+        let message = Arc::new(serde_json::to_string(&msg).unwrap());
+        let dead = match self.clients.get(&uid) {
+            Some(client) => match client.push(message) {
+                Ok(()) => false,
+                Err(err) => {
+                    info!("dropping client {:?}: {:?}", uid, err);
+                    true
+                }
+            },
+            None => false,
+        };
+        if dead {
+            self.clients.remove(&uid);
+        }
+    }
+
+    fn handle_introduce(&mut self, client: NewClient) {
+        let uid = client.uid;
+        self.empty_since = None;
+        if self.users.contains_key(&uid) {
+            self.handle_reattach(client);
+            return;
+        }
+
+        let session = Session::new(client.conn, client.ip);
+        self.resume_tokens.insert(session.token.clone(), uid);
+        self.sessions.insert(uid, session);
+        self.clients.insert(uid, client.outbox);
+        let mut refused = None;
+        let user = match client.identity {
+            Some(identity) => match self.claim_nick(uid, &identity.nick) {
+                Ok(()) => {
+                    let role = self.staff_role(&identity.nick);
+                    User::authenticated(identity, role)
+                },
+                Err(rejection) => {
+                    refused = Some(rejection);
+                    User::anonymous()
+                },
+            },
+            None => User::anonymous(),
+        };
+        let registered = !user.is_anonymous();
+        self.users.insert(uid, user);
+
+        self.send_welcome(uid, false);
+        if registered {
+            self.dispatch_join(uid);
+        }
+        if let Some(rejection) = refused {
+            self.send_error(uid, None, rejection, Some("authenticate".to_string()));
+        }
+    }
+
+    /// Attaches a resumed session to its new connection, quietly: as far as
+    /// everyone else can tell, the user never left.
+    fn handle_reattach(&mut self, client: NewClient) {
+        let uid = client.uid;
+        match self.sessions.get_mut(&uid) {
+            Some(session) => {
+                if session.conn != client.conn {
+                    // yet another connection has claimed the session since;
+                    // dropping this one's Outbox hangs it up
+                    return;
+                }
+                session.detached = false;
+                session.ip = client.ip;
+            },
+            None => return,
+        }
+        info!("{:?} resumed their session", uid);
+        self.clients.insert(uid, client.outbox);
+        self.send_welcome(uid, true);
+    }
+
+    fn send_welcome(&mut self, uid: UserId, resumed: bool) {
+        let resume_token = match self.sessions.get(&uid) {
+            Some(session) => session.token.clone(),
+            None => return,
+        };
+        let now = self.now();
+        let users = self.users.iter()
+            .map(|(&id, user)| api::UserInfo {
+                uid: id,
+                nick: if user.is_anonymous() { None } else { Some(user.nick().to_string()) },
+                role: user.role(),
+            })
+            .collect();
+        let welcome = api::Welcome {
+            when: now,
+            uid: uid,
+            users: users,
+            dj: self.now_playing.as_ref().map(|np| np.item.uid),
+            waitlist: self.dj_queue.iter().cloned().collect(),
+            now_playing: self.now_playing.as_ref().map(|np| np.item.clone()),
+            elapsed: self.now_playing.as_ref().map(|np| now.saturating_sub(np.item.when)),
+            votes: self.now_playing.as_ref().map(|np| np.tally(now, None)),
+            resume_token: resume_token,
+            resumed: resumed,
+        };
+        self.send_to(uid, api::EgressMessage::Welcome(welcome));
+    }
+
+    /// Hands a session over to a reconnecting client. Its old connection,
+    /// if the server hasn't noticed it's dead yet, is hung up. The session
+    /// stays detached until the new connection is introduced, and expires
+    /// as usual if that never happens.
+    fn handle_resume(&mut self, token: &str, conn: u64) -> Option<UserId> {
+        let uid = match self.resume_tokens.get(token) {
+            Some(&uid) => uid,
+            None => return None,
+        };
+        {
+            let session = self.sessions.get_mut(&uid).unwrap();
+            session.conn = conn;
+            session.detached = true;
+        }
+        self.clients.remove(&uid);
+        self.schedule_expiry(uid, conn);
+        Some(uid)
+    }
+
+    /// Notes that a user's connection has closed. They keep their place in
+    /// the room for `resume_grace` seconds in case they reconnect.
+    fn handle_connection_lost(&mut self, uid: UserId, conn: u64) {
+        let current = self.sessions.get(&uid).map_or(false, |s| s.conn == conn);
+        if !current {
+            // a connection which has been replaced, or a user who has left
+            return;
+        }
+        self.clients.remove(&uid);
+        if self.config.resume_grace == 0 {
+            self.handle_disconnect(uid);
+            return;
+        }
+        self.sessions.get_mut(&uid).unwrap().detached = true;
+        self.schedule_expiry(uid, conn);
+    }
+
+    fn schedule_expiry(&mut self, uid: UserId, conn: u64) {
+        let grace_ms = self.config.resume_grace as u64 * 1000;
+        self.timer.schedule(grace_ms, self.tx.clone(), ChanMessage::Expire(uid, conn));
+    }
+
+    /// Starts the clock on closing the room, if it's one started on demand
+    /// and nobody is left in it.
+    fn schedule_reap(&mut self) {
+        if !self.users.is_empty() || !self.sessions.is_empty() {
+            return;
+        }
+        let after = match self.reaper {
+            Some(ref reaper) => reaper.after,
+            None => return,
+        };
+        if self.empty_since.is_none() {
+            self.empty_since = Some(self.now());
+        }
+        self.timer.schedule(after, self.tx.clone(), ChanMessage::Reap);
+    }
+
+    /// Closes the room if it has been empty for long enough, returning
+    /// whether it has.
+    fn handle_reap(&mut self) -> bool {
+        let (after, since) = match (self.reaper.as_ref(), self.empty_since) {
+            (Some(reaper), Some(since)) => (reaper.after, since),
+            _ => return false,
+        };
+        if self.now().saturating_sub(since) < after {
+            // someone came and went since this was scheduled
+            return false;
+        }
+        let closed = (*self.reaper.as_ref().unwrap().close)();
+        if !closed {
+            // a client is on its way in, so look again later
+            self.timer.schedule(after, self.tx.clone(), ChanMessage::Reap);
+        }
+        closed
+    }
+
+    fn handle_expire(&mut self, uid: UserId, conn: u64) {
+        let expired = self.sessions.get(&uid).map_or(false, |s| s.conn == conn && s.detached);
+        if expired {
+            info!("session of {:?} expired", uid);
+            self.handle_disconnect(uid);
+        }
+    }
+
+    fn claim_nick(&mut self, uid: UserId, nick: &str) -> Result<(), Rejection> {
+        try!(nicks::validate(nick, self.config.max_nick_len));
+        try!(self.nicks.reserve(nick, uid));
+        Ok(())
+    }
+
+    /// The role of a user who authenticated as `nick`.
+    fn staff_role(&self, nick: &str) -> api::Role {
+        match self.staff.get(&nicks::nick_key(nick)) {
+            Some(&role) => role,
+            None => self.config.default_role,
+        }
+    }
+
+    fn dispatch_join(&mut self, uid: UserId) {
+        let (nick, role) = match self.users.get(&uid) {
+            Some(user) => (user.nick().to_string(), user.role()),
+            None => return,
+        };
+        let now = self.now();
+        self.dispatch_msg(api::EgressMessage::Join(api::Join {
+            when: now,
+            uid: uid,
+            nick: nick,
+            role: role,
+        }));
+    }
+
+    /// Answers a request: with an `Ack` if it succeeded and had an id, or
+    /// as described in `send_error` if it failed.
+    fn reply(&mut self, uid: UserId, id: Option<u64>, kind: &str, result: Result<(), Rejection>) {
+        match result {
+            Ok(()) => {
+                if let Some(id) = id {
+                    let ack = api::Ack { when: self.now(), id: id };
+                    self.send_to(uid, api::EgressMessage::Ack(ack));
+                }
+            },
+            Err(rejection) => {
+                info!("refused {} from {:?}: {}", kind, uid, rejection.message);
+                self.send_error(uid, id, rejection, Some(kind.to_string()));
+            },
+        }
+    }
+
+    /// Tells a client its request was refused: with a `Nack` if the request
+    /// had an id, or an `Error` otherwise.
+    fn send_error(&mut self,
+                  uid: UserId,
+                  id: Option<u64>,
+                  rejection: Rejection,
+                  request: Option<String>) {
+        let now = self.now();
+        let msg = match id {
+            Some(id) => api::EgressMessage::Nack(api::Nack {
+                when: now,
+                id: id,
+                code: rejection.code,
+                message: rejection.message,
+            }),
+            None => api::EgressMessage::Error(api::Error {
+                when: now,
+                code: rejection.code,
+                message: rejection.message,
+                request: request,
+            }),
+        };
+        self.send_to(uid, msg);
+    }
+
+    fn handle_register(&mut self, uid: UserId, reg: &api::RegisterMessage) -> Result<(), Rejection> {
+        if self.config.require_auth {
+            return Err(Rejection::new(api::ErrorCode::Forbidden, "this room requires authentication"));
+        }
+        if !self.users.get(&uid).unwrap().is_anonymous() {
+            return Err(Rejection::new(api::ErrorCode::Conflict, "already registered"));
+        }
+        try!(self.claim_nick(uid, &reg.nick));
+        // roles given by nick are only for users who can prove who they are
+        let role = self.config.default_role;
+        self.users.insert(uid, User::registered(&reg.nick, role));
+        self.dispatch_join(uid);
+        Ok(())
+    }
+
+    fn handle_nick(&mut self, uid: UserId, msg: &api::NickMessage) -> Result<(), Rejection> {
+        let old_nick = match self.users.get(&uid) {
+            Some(user) if user.is_anonymous() => {
+                return Err(Rejection::new(api::ErrorCode::Forbidden, "register first"));
+            },
+            Some(user) if user.is_authenticated() => {
+                return Err(Rejection::new(api::ErrorCode::Forbidden, "your nick comes from your token"));
+            },
+            Some(user) => user.nick().to_string(),
+            None => return Ok(()),
+        };
+        try!(self.claim_nick(uid, &msg.nick));
+        // a change of case keeps the same reservation
+        if nicks::nick_key(&old_nick) != nicks::nick_key(&msg.nick) {
+            self.nicks.release(&old_nick, uid);
+        }
+        self.users.get_mut(&uid).unwrap().rename(&msg.nick);
+
+        let now = self.now();
+        self.dispatch_msg(api::EgressMessage::NickChange(api::NickChange {
+            when: now,
+            uid: uid,
+            old_nick: old_nick,
+            new_nick: msg.nick.clone(),
+        }));
+        Ok(())
+    }
+
+    /// Checks a connecting client against the room's bans.
+    fn handle_admit(&mut self, ip: IpAddr, nick: Option<String>) -> Result<(), String> {
+        let now = self.now();
+        let key = nick.map(|nick| nicks::nick_key(&nick));
+        match self.bans.find(now, None, Some(ip), key.as_ref().map(|key| &key[..])) {
+            Some(&Sanction { until: Some(until), .. }) => {
+                Err(format!("banned for another {} seconds", (until - now + 999) / 1000))
+            },
+            Some(_) => Err("banned".to_string()),
+            None => Ok(()),
+        }
+    }
+
+    /// Puts a user out of the room, letting their client hear why first.
+    fn remove_user(&mut self, uid: UserId) {
+        if let Some(client) = self.clients.remove(&uid) {
+            client.finish();
+        }
+        self.handle_disconnect(uid);
+    }
+
+    fn dispatch_moderation(&mut self,
+                           action: api::ModAction,
+                           uid: UserId,
+                           by: Option<UserId>,
+                           until: Option<u64>,
+                           reason: Option<String>) {
+        info!("{:?} {} {:?}", by, action.name(), uid);
+        let now = self.now();
+        self.dispatch_msg(api::EgressMessage::Moderation(api::Moderation {
+            when: now,
+            action: action,
+            uid: uid,
+            by: by,
+            until: until,
+            reason: reason,
+        }));
+    }
+
+    /// What a mute on `uid` goes by besides their uid: the address they're
+    /// connecting from, and their nick_key if they've authenticated.
+    fn sanction_keys(&self, uid: UserId) -> (Option<IpAddr>, Option<String>) {
+        let ip = self.sessions.get(&uid).map(|session| session.ip);
+        let nick = match self.users.get(&uid) {
+            Some(user) if user.is_authenticated() => Some(nicks::nick_key(user.nick())),
+            _ => None,
+        };
+        (ip, nick)
+    }
+
+    /// When `uid` may chat again, if they're muted.
+    fn muted_until(&mut self, uid: UserId, now: u64) -> Option<u64> {
+        let (ip, nick) = self.sanction_keys(uid);
+        self.mutes.find(now, Some(uid), ip, nick.as_ref().map(|nick| &nick[..])).and_then(|mute| mute.until)
+    }
+
+    /// Mutes `uid` until `until`, in place of any mute they were under. The
+    /// mute goes by uid, and by nick if they've authenticated; `by_ip` also
+    /// mutes the address they're connecting from, which may well be shared.
+    /// `by` is the role of the staff placing it, if it isn't the room.
+    fn mute(&mut self, uid: UserId, until: u64, by_ip: bool, by: Option<api::Role>) {
+        let (ip, nick) = self.sanction_keys(uid);
+        let role = self.users.get(&uid).map_or(api::Role::Guest, |user| user.role());
+        self.mutes.remove(uid, ip, nick.as_ref().map(|nick| &nick[..]));
+        self.mutes.add(Sanction {
+            uid: uid,
+            nick: nick,
+            ip: if by_ip { ip } else { None },
+            until: Some(until),
+            role: role,
+            by: by,
+        });
+    }
+
+    /// What `uid`'s requests are counted under: their nick_key if they've
+    /// authenticated, or else their uid, which resuming keeps. Rooms set to
+    /// `limit_by_address` count everyone else by address instead.
+    fn limiter_key(&self, uid: UserId) -> String {
+        let (ip, nick) = self.sanction_keys(uid);
+        match (nick, ip) {
+            (Some(nick), _) => format!("nick:{}", nick),
+            (None, Some(ip)) if self.config.limit_by_address => format!("ip:{}", ip),
+            _ => format!("uid:{}", uid.0),
+        }
+    }
+
+    /// Forgets the rate limits of users who haven't made a request in a
+    /// long while.
+    fn prune_limiters(&mut self, now: u64) {
+        let idle: Vec<String> = self.limiters.iter()
+            .filter(|&(_, limiter)| limiter.idle(now))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in idle.iter() {
+            self.limiters.remove(key);
+        }
+    }
+
+    /// Counts a request refused for going over a rate limit against the
+    /// user, muting them once they've done it too often.
+    fn handle_flooding(&mut self, uid: UserId, now: u64) {
+        let key = self.limiter_key(uid);
+        let mute = match self.limiters.get_mut(&key) {
+            Some(limiter) => {
+                let first_mute = self.config.flood_mute as u64 * 1000;
+                limiter.strike(now, self.config.flood_strikes, first_mute)
+            },
+            None => None,
+        };
+        if let Some(duration) = mute {
+            let muted = self.muted_until(uid, now).unwrap_or(0);
+            let until = cmp::max(muted, now.saturating_add(duration));
+            self.mute(uid, until, false, None);
+            let reason = Some("flooding".to_string());
+            self.dispatch_moderation(api::ModAction::Mute, uid, None, Some(until), reason);
+        }
+    }
+
+    fn handle_kick(&mut self, uid: UserId, msg: &api::KickMessage) -> Result<(), Rejection> {
+        if !self.users.contains_key(&msg.uid) {
+            return Err(Rejection::new(api::ErrorCode::NotFound, "no such user"));
+        }
+        self.dispatch_moderation(api::ModAction::Kick, msg.uid, Some(uid), None, msg.reason.clone());
+        self.remove_user(msg.uid);
+        Ok(())
+    }
+
+    fn handle_mute(&mut self, uid: UserId, msg: &api::MuteMessage) -> Result<(), Rejection> {
+        if !self.users.contains_key(&msg.uid) {
+            return Err(Rejection::new(api::ErrorCode::NotFound, "no such user"));
+        }
+        if self.config.max_mute as u64 * 1000 < msg.duration {
+            let msg = format!("mutes may last at most {} seconds", self.config.max_mute);
+            return Err(Rejection::new(api::ErrorCode::Invalid, &msg));
+        }
+        let until = self.now().saturating_add(msg.duration);
+        let by = self.users.get(&uid).map(|user| user.role());
+        self.mute(msg.uid, until, msg.ip.unwrap_or(false), by);
+        self.dispatch_moderation(api::ModAction::Mute, msg.uid, Some(uid), Some(until), msg.reason.clone());
+        Ok(())
+    }
+
+    fn handle_unmute(&mut self, uid: UserId, msg: &api::TargetMessage) -> Result<(), Rejection> {
+        let (ip, nick) = self.sanction_keys(msg.uid);
+        if !self.mutes.remove(msg.uid, ip, nick.as_ref().map(|nick| &nick[..])) {
+            return Err(Rejection::new(api::ErrorCode::NotFound, "user isn't muted"));
+        }
+        self.dispatch_moderation(api::ModAction::Unmute, msg.uid, Some(uid), None, None);
+        Ok(())
+    }
+
+    fn handle_ban(&mut self, uid: UserId, msg: &api::BanMessage) -> Result<(), Rejection> {
+        let (nick, role) = match self.users.get(&msg.uid) {
+            Some(user) if user.is_authenticated() => (Some(nicks::nick_key(user.nick())), user.role()),
+            Some(user) => (None, user.role()),
+            None => return Err(Rejection::new(api::ErrorCode::NotFound, "no such user")),
+        };
+        let by = self.users.get(&uid).map(|user| user.role());
+        let ip = if msg.ip.unwrap_or(false) {
+            self.sessions.get(&msg.uid).map(|session| session.ip)
+        } else {
+            None
+        };
+        if msg.duration.map_or(false, |duration| self.config.max_ban as u64 * 1000 < duration) {
+            let msg = format!("bans may last at most {} seconds, unless they're for good", self.config.max_ban);
+            return Err(Rejection::new(api::ErrorCode::Invalid, &msg));
+        }
+        let now = self.now();
+        let until = msg.duration.map(|duration| now.saturating_add(duration));
+        self.bans.add(Sanction {
+            uid: msg.uid,
+            nick: nick,
+            ip: ip,
+            until: until,
+            role: role,
+            by: by,
+        });
+        self.dispatch_moderation(api::ModAction::Ban, msg.uid, Some(uid), until, msg.reason.clone());
+        self.remove_user(msg.uid);
+        Ok(())
+    }
+
+    fn handle_unban(&mut self, uid: UserId, msg: &api::TargetMessage) -> Result<(), Rejection> {
+        let now = self.now();
+        if !self.bans.lift(now, msg.uid) {
+            return Err(Rejection::new(api::ErrorCode::NotFound, "user isn't banned"));
+        }
+        self.dispatch_moderation(api::ModAction::Unban, msg.uid, Some(uid), None, None);
+        Ok(())
+    }
+
+    fn handle_set_role(&mut self, uid: UserId, msg: &api::SetRoleMessage) -> Result<(), Rejection> {
+        // the policy has already checked the roles involved
+        let staff_nick = match self.users.get(&msg.uid) {
+            Some(user) if user.is_anonymous() => {
+                return Err(Rejection::new(api::ErrorCode::Conflict, "user hasn't registered"));
+            },
+            Some(user) if user.is_authenticated() => Some(user.nick().to_string()),
+            Some(_) => None,
+            None => return Err(Rejection::new(api::ErrorCode::NotFound, "no such user")),
+        };
+        if msg.role == api::Role::Guest {
+            return Err(Rejection::new(api::ErrorCode::Invalid, "registered users can't be made guests"));
+        }
+
+        self.users.get_mut(&msg.uid).unwrap().set_role(msg.role);
+        if let Some(nick) = staff_nick {
+            self.staff.insert(nicks::nick_key(&nick), msg.role);
+        }
+        info!("{:?} made {:?} a {}", uid, msg.uid, msg.role.name());
+
+        let now = self.now();
+        self.dispatch_msg(api::EgressMessage::RoleChange(api::RoleChange {
+            when: now,
+            uid: msg.uid,
+            role: msg.role,
+            by: uid,
+        }));
+        Ok(())
+    }
+
+    fn handle_dj_queue(&mut self, uid: UserId) -> Result<(), Rejection> {
+        if self.dj_queue.iter().any(|&u| u == uid) {
+            return Err(Rejection::new(api::ErrorCode::Conflict, "already in the waitlist"));
+        }
+        if !self.has_track(uid) {
+            return Err(Rejection::new(api::ErrorCode::Conflict, "enqueue a track or activate a playlist first"));
+        }
+        if self.config.max_waitlist <= self.dj_queue.len() {
+            return Err(Rejection::new(api::ErrorCode::LimitReached, "the waitlist is full"));
+        }
+        self.dj_queue.push_back(uid);
+        if self.now_playing.is_none() {
+            self.play_next();
+        } else {
+            self.dispatch_booth();
+        }
+        Ok(())
+    }
+
+    /// Whether `uid` has something to play when their turn comes: a track
+    /// they've enqueued, or one in their active playlist.
+    fn has_track(&self, uid: UserId) -> bool {
+        self.dj_tracks.get(&uid).map_or(false, |tracks| !tracks.is_empty()) || self.has_playlist_track(uid)
+    }
+
+    /// Takes `uid` out of the waitlist if they've been left with nothing to
+    /// play, rather than leaving them to be dropped when their turn comes.
+    fn leave_if_idle(&mut self, uid: UserId) {
+        if self.dj_queue.iter().any(|&u| u == uid) && !self.has_track(uid) {
+            self.handle_dj_unqueue(uid);
+        }
+    }
+
+    /// Looks up a track on a thread of its own, since providers may take a
+    /// while to answer. The request is answered once the lookup comes back,
+    /// in `handle_resolved`.
+    fn resolve(&mut self,
+               uid: UserId,
+               id: Option<u64>,
+               lookup: Lookup,
+               media: &api::MediaRef,
+               claimed: Option<&api::MediaInfo>) {
+        let providers = self.providers.clone();
+        let tx = self.tx.clone();
+        let media = media.clone();
+        let claimed = claimed.cloned();
+        thread::spawn(move || {
+            let result = providers.resolve(&media, claimed.as_ref());
+            let _ = tx.send(ChanMessage::Resolved(uid, id, lookup, media, result));
+        });
+    }
+
+    fn handle_enqueue(&mut self, uid: UserId, id: Option<u64>, msg: &api::EnqueueMessage) -> Result<(), Rejection> {
+        let queued = self.dj_tracks.get(&uid).map_or(0, |tracks| tracks.len());
+        if self.config.max_tracks_per_dj <= queued {
+            return Err(Rejection::new(api::ErrorCode::LimitReached, "your queue is full"));
+        }
+        self.resolve(uid, id, Lookup::Enqueue, &msg.media, msg.info.as_ref());
+        Ok(())
+    }
+
+    fn handle_resolved(&mut self,
+                       uid: UserId,
+                       lookup: Lookup,
+                       media: api::MediaRef,
+                       result: Result<api::MediaInfo, MediaError>) -> Result<(), Rejection> {
+        if !self.users.contains_key(&uid) {
+            return Ok(());
+        }
+        let info = try!(result);
+        let max_duration = media::duration_limit(self.config.max_duration);
+        if max_duration < info.duration {
+            let msg = format!("tracks may be at most {} seconds long", max_duration / 1000);
+            return Err(Rejection::new(api::ErrorCode::Invalid, &msg));
+        }
+        match lookup {
+            Lookup::Enqueue => self.finish_enqueue(uid, media, info),
+            Lookup::PlaylistAdd(playlist) => {
+                let (playlists, owner) = try!(self.playlist_owner(uid));
+                let item = api::PlaylistItem {
+                    media: media,
+                    info: info,
+                };
+                try!(playlists.add(&owner, playlist, item));
+                self.send_playlists(uid);
+                Ok(())
+            },
+        }
+    }
+
+    fn finish_enqueue(&mut self, uid: UserId, media: api::MediaRef, info: api::MediaInfo) -> Result<(), Rejection> {
+        // the queue may have filled up during the lookup
+        let queued = self.dj_tracks.get(&uid).map_or(0, |tracks| tracks.len());
+        if self.config.max_tracks_per_dj <= queued {
+            return Err(Rejection::new(api::ErrorCode::LimitReached, "your queue is full"));
+        }
+
+        self.entry_serial += 1;
+        let eid = self.entry_serial;
+        let now = self.now();
+        let item = api::PlayItem {
+            when: now,
+            uid: uid,
+            eid: eid,
+            media: media.clone(),
+            info: info.clone(),
+        };
+        self.dj_tracks.entry(uid).or_insert_with(VecDeque::new).push_back(item);
+
+        let pbm = api::PlaybackMessage::EnqueueItem(api::EnqueueItem {
+            when: now,
+            uid: uid,
+            eid: eid,
+            media: media,
+            info: info,
+            position: queued,
+        });
+        self.dispatch_msg(api::EgressMessage::PlaybackMessage(pbm));
+        Ok(())
+    }
+
+    fn handle_dequeue(&mut self, uid: UserId, msg: &api::DequeueMessage) -> Result<(), Rejection> {
+        {
+            let tracks = self.dj_tracks.entry(uid).or_insert_with(VecDeque::new);
+            let old = mem::replace(tracks, VecDeque::new());
+            let before = old.len();
+            tracks.extend(old.into_iter().filter(|item| item.eid != msg.eid));
+            if tracks.len() == before {
+                return Err(Rejection::new(api::ErrorCode::NotFound, "no such entry in your queue"));
+            }
+        }
+        let now = self.now();
+        let pbm = api::PlaybackMessage::DequeueItem(api::DequeueItem {
+            when: now,
+            uid: uid,
+            eid: msg.eid,
+        });
+        self.dispatch_msg(api::EgressMessage::PlaybackMessage(pbm));
+        self.leave_if_idle(uid);
+        Ok(())
+    }
+
+    fn handle_reorder(&mut self, uid: UserId, msg: &api::ReorderMessage) -> Result<(), Rejection> {
+        let position = {
+            let tracks = self.dj_tracks.entry(uid).or_insert_with(VecDeque::new);
+            let index = match tracks.iter().position(|item| item.eid == msg.eid) {
+                Some(index) => index,
+                None => return Err(Rejection::new(api::ErrorCode::NotFound, "no such entry in your queue")),
+            };
+            let item = tracks.remove(index).unwrap();
+            let position = cmp::min(msg.position, tracks.len());
+            tracks.insert(position, item);
+            position
+        };
+        let now = self.now();
+        let pbm = api::PlaybackMessage::MoveItem(api::MoveItem {
+            when: now,
+            uid: uid,
+            eid: msg.eid,
+            position: position,
+        });
+        self.dispatch_msg(api::EgressMessage::PlaybackMessage(pbm));
+        Ok(())
+    }
+
+    /// Where `uid`'s playlists are kept, and the key they're kept under.
+    /// Only users who authenticated have their playlists saved, by the
+    /// nick in their token, since anyone can register with any nick that's
+    /// free; other registered users' last until they leave.
+    fn playlist_owner(&self, uid: UserId) -> Result<(Arc<Store>, String), Rejection> {
+        match self.users.get(&uid) {
+            Some(user) if user.is_authenticated() => Ok((self.playlists.clone(), nicks::nick_key(user.nick()))),
+            Some(user) if !user.is_anonymous() => Ok((self.session_playlists.clone(), session_key(uid))),
+            _ => Err(Rejection::new(api::ErrorCode::Forbidden, "register to keep playlists")),
+        }
+    }
+
+    fn has_playlist_track(&self, uid: UserId) -> bool {
+        let max_duration = media::duration_limit(self.config.max_duration);
+        self.playlist_owner(uid).map(|(playlists, owner)| playlists.has_next(&owner, max_duration)).unwrap_or(false)
+    }
+
+    fn send_playlists(&mut self, uid: UserId) {
+        let saved = match self.playlist_owner(uid) {
+            Ok((playlists, owner)) => playlists.get(&owner),
+            Err(_) => return,
+        };
+        let now = self.now();
+        self.send_to(uid, api::EgressMessage::Playlists(api::Playlists {
+            when: now,
+            active: saved.active,
+            playlists: saved.playlists,
+        }));
+    }
+
+    /// Carries out any playlist request but `playlist_add`, then sends the
+    /// user their playlists as they now stand.
+    fn handle_playlist_request(&mut self, uid: UserId, msg: &api::IngressMessage) -> Result<(), Rejection> {
+        use api::IngressMessage as IM;
+        let (playlists, owner) = try!(self.playlist_owner(uid));
+        let result = match *msg {
+            IM::PlaylistCreate(ref req) => playlists.create(&owner, &req.name).map(|_| ()),
+            IM::PlaylistRename(ref req) => playlists.rename(&owner, req.playlist, &req.name),
+            IM::PlaylistDelete(ref req) => playlists.delete(&owner, req.playlist),
+            IM::PlaylistRemove(ref req) => playlists.remove(&owner, req.playlist, req.index),
+            IM::PlaylistMove(ref req) => playlists.move_item(&owner, req.playlist, req.from, req.to),
+            IM::PlaylistShuffle(ref req) => playlists.shuffle(&owner, req.playlist),
+            IM::PlaylistActivate(ref req) => playlists.activate(&owner, req.playlist),
+            // just asking to see them
+            _ => Ok(()),
+        };
+        try!(result);
+        self.send_playlists(uid);
+        self.leave_if_idle(uid);
+        Ok(())
+    }
+
+    fn handle_playlist_add(&mut self, uid: UserId, id: Option<u64>, msg: &api::PlaylistAddMessage) -> Result<(), Rejection> {
+        let (playlists, owner) = try!(self.playlist_owner(uid));
+        if !playlists.get(&owner).playlists.iter().any(|p| p.id == msg.playlist) {
+            return Err(Rejection::new(api::ErrorCode::NotFound, "no such playlist"));
+        }
+        self.resolve(uid, id, Lookup::PlaylistAdd(msg.playlist), &msg.media, msg.info.as_ref());
+        Ok(())
+    }
+
+    /// Imports a playlist on a thread of its own, since every track in it
+    /// is looked up. The request is answered in `handle_imported`.
+    fn handle_playlist_import(&mut self, uid: UserId, id: Option<u64>, msg: &api::ImportPlaylistMessage) -> Result<(), Rejection> {
+        let (playlists, owner) = try!(self.playlist_owner(uid));
+        let import = Import {
+            format: msg.format,
+            data: msg.data.clone(),
+            name: msg.name.clone(),
+            dry_run: msg.dry_run.unwrap_or(false),
+            max_duration: media::duration_limit(self.config.max_duration),
+        };
+        let providers = self.providers.clone();
+        let tx = self.tx.clone();
+        thread::spawn(move || {
+            let result = playlists.import(&providers, &owner, &import);
+            let _ = tx.send(ChanMessage::Imported(uid, id, result));
+        });
+        Ok(())
+    }
+
+    fn handle_imported(&mut self, uid: UserId, result: Result<api::ImportReport, PlaylistError>) -> Result<(), Rejection> {
+        if !self.users.contains_key(&uid) {
+            // they left while it was underway, taking any session playlists
+            // with them
+            self.session_playlists.forget(&session_key(uid));
+            return Ok(());
+        }
+        let mut report = try!(result);
+        report.when = self.now();
+        let dry_run = report.dry_run;
+        self.send_to(uid, api::EgressMessage::ImportReport(report));
+        if !dry_run {
+            self.send_playlists(uid);
+        }
+        Ok(())
+    }
+
+    fn handle_playlist_export(&mut self, uid: UserId, msg: &api::ExportPlaylistMessage) -> Result<(), Rejection> {
+        let (playlists, owner) = try!(self.playlist_owner(uid));
+        let data = try!(playlists.export(&owner, msg.playlist, msg.format));
+        let now = self.now();
+        self.send_to(uid, api::EgressMessage::PlaylistExport(api::PlaylistExport {
+            when: now,
+            playlist: msg.playlist,
+            format: msg.format,
+            data: data,
+        }));
+        Ok(())
+    }
+
+    fn handle_dj_unqueue(&mut self, uid: UserId) {
+        let old = mem::replace(&mut self.dj_queue, VecDeque::new());
+        self.dj_queue.extend(old.into_iter().filter(|&u| u != uid));
+        self.dispatch_booth();
+    }
+
+    fn handle_disconnect(&mut self, uid: UserId) {
+        self.clients.remove(&uid);
+        let user = match self.users.remove(&uid) {
+            Some(user) => user,
+            // already gone
+            None => return,
+        };
+        if !user.is_anonymous() {
+            self.nicks.release(user.nick(), uid);
+        }
+        if let Some(session) = self.sessions.remove(&uid) {
+            self.resume_tokens.remove(&session.token);
+        }
+        self.dj_tracks.remove(&uid);
+        self.session_playlists.forget(&session_key(uid));
+        if self.dj_queue.iter().any(|&u| u == uid) {
+            self.handle_dj_unqueue(uid);
+        }
+        let now = self.now();
+        self.prune_limiters(now);
+        self.dispatch_msg(api::EgressMessage::Part(api::Part {
+            when: now,
+            uid: uid,
+        }));
+
+        // their vote and grab leave with them
+        let tally = match self.now_playing {
+            Some(ref mut np) => {
+                let voted = np.votes.remove(&uid).is_some();
+                let grabbed = np.grabs.remove(&uid);
+                if voted || grabbed { Some(np.tally(now, None)) } else { None }
+            },
+            None => None,
+        };
+        if let Some(tally) = tally {
+            let pbm = api::PlaybackMessage::Votes(tally);
+            self.dispatch_msg(api::EgressMessage::PlaybackMessage(pbm));
+        }
+        // with one listener fewer, the mehs may now be enough
+        self.skip_if_voted_off();
+        self.schedule_reap();
+    }
+
+    fn dispatch_booth(&mut self) {
+        let booth = api::Booth {
+            when: self.now(),
+            dj: self.now_playing.as_ref().map(|np| np.item.uid),
+            waitlist: self.dj_queue.iter().cloned().collect(),
+        };
+        let pbm = api::PlaybackMessage::Booth(booth);
+        self.dispatch_msg(api::EgressMessage::PlaybackMessage(pbm));
+    }
+
+    /// Takes the next track of the DJ at the front of the dj_queue, rotating
+    /// them to the back. Tracks they've enqueued come first, then their
+    /// active playlist. DJs with nothing left to play, this track included,
+    /// give up their spot.
+    fn next_from_booth(&mut self) -> Option<api::PlayItem> {
+        while let Some(dj) = self.dj_queue.pop_front() {
+            let track = match self.dj_tracks.get_mut(&dj).and_then(|t| t.pop_front()) {
+                Some(item) => Some(item),
+                None => self.next_from_playlist(dj),
+            };
+            if let Some(item) = track {
+                if self.has_track(dj) {
+                    self.dj_queue.push_back(dj);
+                }
+                return Some(item);
+            }
+        }
+        None
+    }
+
+    /// Takes the next track of `dj`'s active playlist that isn't too long
+    /// for the room, moving it to the back of the playlist.
+    fn next_from_playlist(&mut self, dj: UserId) -> Option<api::PlayItem> {
+        let (playlists, owner) = match self.playlist_owner(dj) {
+            Ok(found) => found,
+            Err(_) => return None,
+        };
+        let max_duration = media::duration_limit(self.config.max_duration);
+        let track = match playlists.next_track(&owner, max_duration) {
+            Some(track) => track,
+            None => return None,
+        };
+        self.entry_serial += 1;
+        Some(api::PlayItem {
+            when: self.now(),
+            uid: dj,
+            eid: self.entry_serial,
+            media: track.media,
+            info: track.info,
+        })
+    }
+
+    /// Starts the next track from the DJ rotation, or leaves the room idle
+    /// if there is nothing left to play.
+    fn play_next(&mut self) {
+        match self.next_from_booth() {
+            Some(item) => self.play(item),
+            None => self.now_playing = None,
+        }
+        self.dispatch_booth();
+    }
+
+    fn play(&mut self, mut item: api::PlayItem) {
+        self.play_serial += 1;
+        let serial = self.play_serial;
+        item.when = self.now();
+
+        self.timer.schedule(item.info.duration, self.tx.clone(), ChanMessage::TrackEnd(serial));
+
+        self.now_playing = Some(NowPlaying {
+            serial: serial,
+            item: item.clone(),
+            votes: HashMap::new(),
+            grabs: HashSet::new(),
+        });
+        let pbm = api::PlaybackMessage::PlayItem(item);
+        self.dispatch_msg(api::EgressMessage::PlaybackMessage(pbm));
+    }
+
+    fn handle_track_end(&mut self, serial: u64) {
+        match self.now_playing {
+            Some(ref np) if np.serial == serial => (),
+            // a timer for a track which was skipped
+            _ => return,
+        }
+        self.finish_play(false);
+        self.play_next();
+    }
+
+    fn handle_clock(&mut self, uid: UserId, req: &api::ClockRequest) {
+        let received = self.now();
+        let reply = api::Clock {
+            when: self.now(),
+            origin: Some(req.when),
+            received: Some(received),
+        };
+        self.send_to(uid, api::EgressMessage::Clock(reply));
+    }
+
+    fn handle_skip(&mut self, uid: UserId) -> Result<(), Rejection> {
+        if self.now_playing.is_none() {
+            return Err(Rejection::new(api::ErrorCode::NotFound, "nothing is playing"));
+        }
+        self.skip(Some(uid));
+        Ok(())
+    }
+
+    fn skip(&mut self, by: Option<UserId>) {
+        let pbm = api::PlaybackMessage::Skip(api::Skip {
+            when: self.now(),
+            uid: by,
+        });
+        self.dispatch_msg(api::EgressMessage::PlaybackMessage(pbm));
+        self.finish_play(true);
+        self.play_next();
+    }
+
+    /// Records the final score of the current play in the history, and
+    /// announces it.
+    fn finish_play(&mut self, skipped: bool) {
+        let now = self.now();
+        let record = match self.now_playing {
+            Some(ref np) => {
+                let tally = np.tally(now, None);
+                api::PlayRecord {
+                    when: now,
+                    item: np.item.clone(),
+                    skipped: skipped,
+                    woots: tally.woots,
+                    mehs: tally.mehs,
+                    grabs: tally.grabs,
+                }
+            },
+            None => return,
+        };
+        self.history.push_back(record.clone());
+        while self.config.history_len < self.history.len() {
+            self.history.pop_front();
+        }
+        let pbm = api::PlaybackMessage::Played(record);
+        self.dispatch_msg(api::EgressMessage::PlaybackMessage(pbm));
+    }
+
+    fn handle_history(&mut self, uid: UserId) {
+        let now = self.now();
+        let history = api::History {
+            when: now,
+            plays: self.history.iter().cloned().collect(),
+        };
+        self.send_to(uid, api::EgressMessage::History(history));
+    }
+
+    fn handle_grab(&mut self, uid: UserId) -> Result<(), Rejection> {
+        let now = self.now();
+        let tally = match self.now_playing {
+            Some(ref mut np) => {
+                if !np.grabs.insert(uid) {
+                    return Err(Rejection::new(api::ErrorCode::Conflict, "already grabbed"));
+                }
+                np.tally(now, Some(uid))
+            },
+            None => return Err(Rejection::new(api::ErrorCode::NotFound, "nothing is playing")),
+        };
+        let pbm = api::PlaybackMessage::Votes(tally);
+        self.dispatch_msg(api::EgressMessage::PlaybackMessage(pbm));
+        Ok(())
+    }
+
+    fn handle_vote(&mut self, uid: UserId, msg: &api::VoteMessage) -> Result<(), Rejection> {
+        let now = self.now();
+        let tally = match self.now_playing {
+            Some(ref mut np) => {
+                if np.votes.insert(uid, msg.vote) == Some(msg.vote) {
+                    // nothing's changed
+                    return Ok(());
+                }
+                np.tally(now, Some(uid))
+            },
+            None => return Err(Rejection::new(api::ErrorCode::NotFound, "nothing is playing")),
+        };
+        let pbm = api::PlaybackMessage::Votes(tally);
+        self.dispatch_msg(api::EgressMessage::PlaybackMessage(pbm));
+        self.skip_if_voted_off();
+        Ok(())
+    }
+
+    /// Whether `id` is a listener: a registered user who is connected,
+    /// other than the DJ.
+    fn is_listener(&self, id: UserId) -> bool {
+        let dj = self.now_playing.as_ref().map(|np| np.item.uid);
+        Some(id) != dj &&
+            self.clients.contains_key(&id) &&
+            self.users.get(&id).map_or(false, |user| !user.is_anonymous())
+    }
+
+    /// Whether mehs from listeners are over `meh_skip_percent` of them.
+    /// Votes from users who have dropped out don't count, so that nobody
+    /// can vote twice by reconnecting.
+    fn voted_off(&self) -> bool {
+        let percent = self.config.meh_skip_percent as u64;
+        let np = match self.now_playing {
+            Some(ref np) if percent != 0 => np,
+            _ => return false,
+        };
+        let listeners = self.users.keys().filter(|&&id| self.is_listener(id)).count() as u64;
+        let mehs = np.votes.iter()
+            .filter(|&(&id, &vote)| vote == api::Vote::Meh && self.is_listener(id))
+            .count() as u64;
+        0 < listeners && listeners * percent < mehs * 100
+    }
+
+    fn skip_if_voted_off(&mut self) {
+        if self.voted_off() {
+            info!("the room voted off play {}", self.play_serial);
+            self.skip(None);
+        }
+    }
+
+    fn handle_message(&mut self, uid: UserId, msg: &str) -> Result<(), Rejection> {
+        let now = self.now();
+        let key = self.limiter_key(uid);
+        if let Some(limiter) = self.limiters.get_mut(&key) {
+            limiter.last_message = Some(now);
+        }
+        self.dispatch_msg(api::EgressMessage::UserMessage(api::UserMessage {
+            when: now,
+            uid: uid,
+            body: msg.to_string(),
+        }));
+        Ok(())
+    }
+
+    pub fn handle_msg(&mut self, uid: UserId, frame: &api::IngressFrame) {
+        let result = self.handle_request(uid, frame.id, &frame.request);
+        // requests which got as far as looking up tracks are answered once
+        // the lookups come back
+        let deferred = match frame.request {
+            api::IngressMessage::Enqueue(_) |
+            api::IngressMessage::PlaylistAdd(_) |
+            api::IngressMessage::PlaylistImport(_) => result.is_ok(),
+            _ => false,
+        };
+        if !deferred {
+            self.reply(uid, frame.id, frame.request.kind(), result);
+        }
+    }
+
+    fn handle_authenticated(&mut self, uid: UserId, result: Result<Identity, Rejection>) -> Result<(), Rejection> {
+        let identity = try!(result);
+        let now = self.now();
+        if self.bans.find(now, None, None, Some(&nicks::nick_key(&identity.nick))).is_some() {
+            return Err(Rejection::new(api::ErrorCode::Forbidden, "you are banned from this room"));
+        }
+        match self.users.get(&uid) {
+            Some(user) if !user.is_anonymous() => {
+                return Err(Rejection::new(api::ErrorCode::Conflict, "already registered"));
+            },
+            Some(_) => (),
+            None => return Ok(()),
+        }
+        try!(self.claim_nick(uid, &identity.nick));
+        let role = self.staff_role(&identity.nick);
+        self.users.insert(uid, User::authenticated(identity, role));
+        self.dispatch_join(uid);
+        Ok(())
+    }
+
+    fn handle_request(&mut self, uid: UserId, id: Option<u64>, msg: &api::IngressMessage) -> Result<(), Rejection> {
+        use api::IngressMessage as IM;
+        let role = match self.users.get(&uid) {
+            Some(user) => user.role(),
+            None => {
+                warn!("{:?} not found. dropping message", uid);
+                return Ok(());
+            }
+        };
+        let now = self.now();
+        let key = self.limiter_key(uid);
+        let limited = {
+            let limiter = self.limiters.entry(key.clone()).or_insert_with(Limiter::new);
+            !limiter.allow(&self.config.rate_limits, msg.kind(), now)
+        };
+        if limited {
+            self.handle_flooding(uid, now);
+            return Err(Rejection::new(api::ErrorCode::RateLimited, "slow down"));
+        }
+
+        let muted_until = self.muted_until(uid, now);
+        // what an unmute or unban would lift: who it's on, with their role
+        // at the time, and the role of whoever placed it
+        let lifted = match *msg {
+            IM::Unmute(ref req) => {
+                let (ip, nick) = self.sanction_keys(req.uid);
+                self.mutes.find(now, Some(req.uid), ip, nick.as_ref().map(|nick| &nick[..]))
+                    .map(|mute| ((mute.uid, mute.role), mute.by))
+            },
+            IM::Unban(ref req) => {
+                self.bans.find(now, Some(req.uid), None, None).map(|ban| ((ban.uid, ban.role), ban.by))
+            },
+            _ => None,
+        };
+        let decision = {
+            let dj = self.now_playing.as_ref().map(|np| np.item.uid);
+            let target = match *msg {
+                IM::SetRole(ref req) => Some(req.uid),
+                IM::Kick(ref req) => Some(req.uid),
+                IM::Mute(ref req) => Some(req.uid),
+                IM::Unmute(ref req) => Some(req.uid),
+                IM::Ban(ref req) => Some(req.uid),
+                IM::Skip => dj,
+                _ => None,
+            };
+            let ctx = policy::Context {
+                actor: uid,
+                role: role,
+                target: target.and_then(|t| self.users.get(&t).map(|user| (t, user.role())))
+                    .or(lifted.map(|(target, _)| target)),
+                sanctioned_by: lifted.and_then(|(_, by)| by),
+                dj: dj,
+                muted_until: muted_until,
+                last_message: self.limiters.get(&key).and_then(|limiter| limiter.last_message),
+                now: now,
+                config: &self.config,
+            };
+            self.policy.decide(&ctx, msg)
+        };
+        if let Decision::Deny(rejection) = decision {
+            return Err(rejection);
+        }
+
+        match *msg {
+            IM::Register(ref reg) => self.handle_register(uid, reg),
+            IM::Disconnect => {
+                self.handle_disconnect(uid);
+                Ok(())
+            },
+            IM::Skip => self.handle_skip(uid),
+            IM::Part => Ok(()),
+            IM::DjQueue => self.handle_dj_queue(uid),
+            IM::DjUnqueue => {
+                self.handle_dj_unqueue(uid);
+                Ok(())
+            },
+            IM::Message(ref msg) => self.handle_message(uid, msg),
+            IM::Clock(ref req) => {
+                self.handle_clock(uid, req);
+                Ok(())
+            },
+            IM::Nick(ref msg) => self.handle_nick(uid, msg),
+            IM::Vote(ref msg) => self.handle_vote(uid, msg),
+            IM::Enqueue(ref msg) => self.handle_enqueue(uid, id, msg),
+            IM::Dequeue(ref msg) => self.handle_dequeue(uid, msg),
+            IM::Reorder(ref msg) => self.handle_reorder(uid, msg),
+            IM::Grab => self.handle_grab(uid),
+            IM::Playlists |
+            IM::PlaylistCreate(_) |
+            IM::PlaylistRename(_) |
+            IM::PlaylistDelete(_) |
+            IM::PlaylistRemove(_) |
+            IM::PlaylistMove(_) |
+            IM::PlaylistShuffle(_) |
+            IM::PlaylistActivate(_) => self.handle_playlist_request(uid, msg),
+            IM::PlaylistAdd(ref msg) => self.handle_playlist_add(uid, id, msg),
+            IM::PlaylistImport(ref msg) => self.handle_playlist_import(uid, id, msg),
+            IM::PlaylistExport(ref msg) => self.handle_playlist_export(uid, msg),
+            IM::History => {
+                self.handle_history(uid);
+                Ok(())
+            },
+            IM::SetRole(ref msg) => self.handle_set_role(uid, msg),
+            IM::Kick(ref msg) => self.handle_kick(uid, msg),
+            IM::Mute(ref msg) => self.handle_mute(uid, msg),
+            IM::Unmute(ref msg) => self.handle_unmute(uid, msg),
+            IM::Ban(ref msg) => self.handle_ban(uid, msg),
+            IM::Unban(ref msg) => self.handle_unban(uid, msg),
+            // tokens are checked by the client's thread, which only passes
+            // these along when there is nothing to check them against
+            IM::Authenticate(_) => {
+                Err(Rejection::new(api::ErrorCode::Unavailable, "authentication is not enabled"))
+            },
+        }
+    }
+
+    pub fn run(mut self) {
+        loop {
+            let message = ret_err!(self.rx.recv());
+            match message {
+                ChanMessage::Introduce(client) => {
+                    self.handle_introduce(client)
+                },
+                ChanMessage::Admit(ip, nick, reply) => {
+                    let _ = reply.send(self.handle_admit(ip, nick));
+                },
+                ChanMessage::Resume(token, conn, reply) => {
+                    let uid = self.handle_resume(&token, conn);
+                    let _ = reply.send(uid);
+                },
+                ChanMessage::Status(body) => {
+                    let now = self.now();
+                    self.dispatch_msg(api::EgressMessage::StatusMessage(api::StatusMessage {
+                        when: now,
+                        body: body,
+                    }))
+                },
+                ChanMessage::Message(uid, msg) => {
+                    self.handle_msg(uid, &msg)
+                },
+                ChanMessage::Invalid(uid, id, rejection, request) => {
+                    self.send_error(uid, id, rejection, request)
+                },
+                ChanMessage::Disconnected(uid, conn) => {
+                    self.handle_connection_lost(uid, conn)
+                },
+                ChanMessage::Expire(uid, conn) => {
+                    self.handle_expire(uid, conn)
+                },
+                ChanMessage::Authenticated(uid, id, result) => {
+                    let result = self.handle_authenticated(uid, result);
+                    self.reply(uid, id, "authenticate", result);
+                },
+                ChanMessage::TrackEnd(serial) => {
+                    self.handle_track_end(serial)
+                },
+                ChanMessage::Imported(uid, id, result) => {
+                    let result = self.handle_imported(uid, result);
+                    self.reply(uid, id, "playlist_import", result);
+                },
+                ChanMessage::Resolved(uid, id, lookup, media, result) => {
+                    let kind = lookup.kind();
+                    let result = self.handle_resolved(uid, lookup, media, result);
+                    self.reply(uid, id, kind, result);
+                },
+                ChanMessage::Reap => {
+                    if self.handle_reap() {
+                        return;
+                    }
+                },
+            }
+        }
+    }
+}
+
+
+pub fn start_channel(timebase: clock::Timebase,
+                     timer: Timer,
+                     providers: Arc<Providers>,
+                     playlists: Arc<Store>,
+                     config: RoomConfig,
+                     reaper: Option<Reaper>) -> mpsc::Sender<ChanMessage> {
+    let (tx, rx) = mpsc::channel();
+    let chan_tx = tx.clone();
+    thread::spawn(move || {
+        let mut channel = Channel::new(timebase, timer, providers, playlists, config, reaper, chan_tx, rx);
+        // nobody may turn up in a room started on demand after all
+        channel.schedule_reap();
+        channel.run()
+    });
+    tx
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{mpsc, Arc};
+
+    use api::{self, MediaInfo, MediaRef, ProviderKind};
+    use clock;
+    use config::Config;
+    use media::Providers;
+    use playlists::Store;
+    use timer::Timer;
+    use super::Channel;
+    use super::super::{User, UserId};
+
+    fn channel() -> Channel {
+        let (tx, rx) = mpsc::channel();
+        Channel::new(clock::Timebase::new(),
+                     Timer::start(),
+                     Arc::new(Providers::from_config(&HashMap::new(), None, None)),
+                     Arc::new(Store::new(None, 25, 500)),
+                     Config::default().room_defaults,
+                     None,
+                     tx,
+                     rx)
+    }
+
+    fn dj(channel: &mut Channel, uid: UserId, nick: &str, tracks: usize) {
+        channel.users.insert(uid, User::registered(nick, api::Role::User));
+        for n in 0..tracks {
+            let media = MediaRef {
+                provider: ProviderKind::Youtube,
+                id: format!("{}-{}", nick, n),
+            };
+            let info = MediaInfo {
+                title: format!("Track {}", n),
+                artist: None,
+                duration: 200 * 1000,
+                thumbnail: None,
+                stream_url: None,
+            };
+            channel.finish_enqueue(uid, media, info).unwrap();
+        }
+    }
+
+    #[test]
+    fn djs_leave_the_waitlist_once_their_last_track_starts() {
+        let mut channel = channel();
+        let (alice, bob) = (UserId(1), UserId(2));
+        dj(&mut channel, alice, "alice", 1);
+        dj(&mut channel, bob, "bob", 2);
+
+        channel.handle_dj_queue(alice).unwrap();
+        assert_eq!(channel.now_playing.as_ref().map(|np| np.item.uid), Some(alice));
+        assert!(channel.dj_queue.is_empty());
+
+        channel.handle_dj_queue(bob).unwrap();
+        channel.play_next();
+        assert_eq!(channel.now_playing.as_ref().map(|np| np.item.uid), Some(bob));
+        assert_eq!(channel.dj_queue.iter().cloned().collect::<Vec<_>>(), vec![bob]);
+    }
+}
@@ -0,0 +1,25 @@
+use time;
+
+/// Hands out timestamps in milliseconds since the unix epoch, as used in the
+/// `when` fields of the api. The wall clock is only read once; everything
+/// after that is measured with the monotonic clock, so `when` values never
+/// run backwards when the system clock is adjusted.
+#[derive(Copy, Clone)]
+pub struct Timebase {
+    epoch_ms: u64,
+    mono_ns: u64,
+}
+
+impl Timebase {
+    pub fn new() -> Timebase {
+        let wall = time::get_time();
+        Timebase {
+            epoch_ms: wall.sec as u64 * 1000 + wall.nsec as u64 / 1_000_000,
+            mono_ns: time::precise_time_ns(),
+        }
+    }
+
+    pub fn now(&self) -> u64 {
+        self.epoch_ms + (time::precise_time_ns() - self.mono_ns) / 1_000_000
+    }
+}
@@ -0,0 +1,666 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use log::LogLevelFilter;
+use toml;
+
+use api::{ProviderKind, Role};
+use outbox::OverflowPolicy;
+use policy::{self, Permissions};
+use ratelimit::RateLimit;
+use rooms;
+
+/// Settings which can be given per room. Rooms which aren't listed in the
+/// config file use the defaults.
+#[derive(Clone, Debug)]
+pub struct RoomConfig {
+    pub slug: String,
+    // the role users get once they register
+    pub default_role: Role,
+    // roles of particular users, by the nick they authenticate as
+    pub roles: BTreeMap<String, Role>,
+    // which role each kind of request needs
+    pub permissions: Permissions,
+    // how many DJs may wait in the dj_queue
+    pub max_waitlist: usize,
+    // how many tracks each DJ may have queued
+    pub max_tracks_per_dj: usize,
+    // the play is skipped once more than this percentage of listeners
+    // have voted meh; 0 never skips
+    pub meh_skip_percent: usize,
+    // how many past plays the room remembers
+    pub history_len: usize,
+    // longest chat message accepted, in bytes
+    pub max_message_len: usize,
+    // how often each user may make each kind of request
+    pub rate_limits: BTreeMap<String, RateLimit>,
+    // seconds users below bouncer must wait between messages; 0 for none
+    pub slow_mode: usize,
+    // how many times in a minute a user may go over a rate limit before
+    // being muted; 0 never mutes
+    pub flood_strikes: usize,
+    // seconds a flooding user is first muted for, doubling each time after
+    pub flood_mute: usize,
+    // whether users who haven't authenticated share rate limits with
+    // everyone on the same address, rather than having their own
+    pub limit_by_address: bool,
+    // longest mute, and longest ban short of a permanent one, that staff
+    // may hand out, in seconds
+    pub max_mute: usize,
+    pub max_ban: usize,
+    // longest nick accepted, in characters
+    pub max_nick_len: usize,
+    // whether users must authenticate rather than register with a bare nick
+    pub require_auth: bool,
+    // how long a user whose connection drops keeps their place, in seconds
+    pub resume_grace: usize,
+    // longest track which may be enqueued, in seconds; 0 for the most
+    // the server allows, a day
+    pub max_duration: usize,
+}
+
+fn default_rate_limits() -> BTreeMap<String, RateLimit> {
+    let mut limits = BTreeMap::new();
+    limits.insert("message".to_string(), RateLimit::new(5, 1000));
+    limits.insert("nick".to_string(), RateLimit::new(2, 30 * 1000));
+    limits.insert("dj_queue".to_string(), RateLimit::new(3, 5 * 1000));
+    limits.insert("enqueue".to_string(), RateLimit::new(10, 1000));
+    limits.insert("playlist_add".to_string(), RateLimit::new(10, 1000));
+    limits.insert("playlist_import".to_string(), RateLimit::new(2, 60 * 1000));
+    limits
+}
+
+impl RoomConfig {
+    fn defaults() -> RoomConfig {
+        RoomConfig {
+            slug: String::new(),
+            default_role: Role::User,
+            roles: BTreeMap::new(),
+            permissions: Permissions::new(),
+            max_waitlist: 50,
+            max_tracks_per_dj: 100,
+            meh_skip_percent: 50,
+            history_len: 50,
+            max_message_len: 1024,
+            rate_limits: default_rate_limits(),
+            slow_mode: 0,
+            flood_strikes: 3,
+            flood_mute: 60,
+            limit_by_address: false,
+            max_mute: 7 * 24 * 60 * 60,
+            max_ban: 365 * 24 * 60 * 60,
+            max_nick_len: 32,
+            require_auth: false,
+            resume_grace: 60,
+            max_duration: 20 * 60,
+        }
+    }
+
+    /// The settings for a room which isn't listed in the config file.
+    pub fn for_slug(&self, slug: &str) -> RoomConfig {
+        let mut room = self.clone();
+        room.slug = slug.to_string();
+        room
+    }
+}
+
+/// How bearer tokens are checked.
+#[derive(Clone, Debug)]
+pub enum AuthConfig {
+    // tokens are refused
+    Disabled,
+    // tokens we issued ourselves, signed with a shared secret
+    Hmac { secret: String },
+    // tokens checked against an OAuth2 introspection endpoint
+    Introspection {
+        url: String,
+        client_id: Option<String>,
+        client_secret: Option<String>,
+    },
+}
+
+/// How tracks from one kind of provider are looked up.
+#[derive(Clone, Debug)]
+pub enum ProviderConfig {
+    // whatever the client says about the track goes
+    Client,
+    // made-up tracks, for trying clients out
+    Fake,
+    // tracks looked up with a metadata service
+    Http { endpoint: String },
+    // the server's own library of audio files
+    Library,
+}
+
+/// Where the server's own audio files are.
+#[derive(Clone, Debug)]
+pub struct LibraryConfig {
+    pub dir: String,
+}
+
+/// Where library tracks are streamed from and playlists imported and
+/// exported over plain HTTP.
+#[derive(Clone, Debug)]
+pub struct HttpConfig {
+    pub bind: String,
+    // how clients reach that address, e.g. "http://192.168.1.10:2795"
+    pub url: String,
+}
+
+/// Where what providers say about tracks is remembered between lookups.
+#[derive(Clone, Debug)]
+pub struct MediaCacheConfig {
+    pub dir: String,
+    // how long a track which was found is remembered, in seconds
+    pub ttl: usize,
+    // how long a track which wasn't found is remembered, in seconds
+    pub miss_ttl: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub bind: String,
+    // websocket subprotocol we'll agree to, if the client offers it
+    pub protocol: String,
+    pub log_level: LogLevelFilter,
+    // largest frame accepted from a client, in bytes
+    pub max_frame_len: usize,
+    // how many messages may wait to be written to a client
+    pub outbound_queue_len: usize,
+    // what to do with a client whose outbound queue is full
+    pub overflow_policy: OverflowPolicy,
+    // whether connecting to an unlisted room creates it
+    pub dynamic_rooms: bool,
+    // most rooms running at once, listed ones included; rooms which would
+    // go over it aren't started
+    pub max_rooms: usize,
+    // how long a room which wasn't listed may be empty before it's closed,
+    // in seconds
+    pub empty_room_ttl: usize,
+    pub auth: AuthConfig,
+    // kinds of provider which can't be played from aren't listed
+    pub providers: HashMap<ProviderKind, ProviderConfig>,
+    // not caching if None
+    pub media_cache: Option<MediaCacheConfig>,
+    pub library: Option<LibraryConfig>,
+    // not serving HTTP if None
+    pub http: Option<HttpConfig>,
+    // where users' playlists are saved; they're forgotten on restart if None
+    pub data_dir: Option<String>,
+    // how many playlists each user may have, and how many tracks in each
+    pub max_playlists: usize,
+    pub max_playlist_len: usize,
+    pub room_defaults: RoomConfig,
+    pub rooms: Vec<RoomConfig>,
+}
+
+fn default_providers() -> HashMap<ProviderKind, ProviderConfig> {
+    let mut providers = HashMap::new();
+    providers.insert(ProviderKind::Youtube, ProviderConfig::Client);
+    providers.insert(ProviderKind::Soundcloud, ProviderConfig::Client);
+    providers.insert(ProviderKind::Url, ProviderConfig::Client);
+    providers
+}
+
+fn load_provider(table: &toml::Table) -> Result<Option<ProviderConfig>, ConfigError> {
+    let mut kind = String::new();
+    try!(get_str(table, "kind", &mut kind));
+    match &kind[..] {
+        "disabled" => Ok(None),
+        "client" => Ok(Some(ProviderConfig::Client)),
+        "fake" => Ok(Some(ProviderConfig::Fake)),
+        "library" => Ok(Some(ProviderConfig::Library)),
+        "http" => {
+            let endpoint = try!(try!(get_opt_str(table, "endpoint"))
+                .ok_or(invalid("providers.*.endpoint", "given for http providers")));
+            Ok(Some(ProviderConfig::Http { endpoint: endpoint }))
+        },
+        _ => Err(invalid("providers.*.kind", "one of disabled, client, fake, http or library")),
+    }
+}
+
+fn load_media_cache(table: &toml::Table) -> Result<MediaCacheConfig, ConfigError> {
+    let dir = try!(try!(get_opt_str(table, "dir"))
+        .ok_or(invalid("media_cache.dir", "given")));
+    let mut cache = MediaCacheConfig {
+        dir: dir,
+        ttl: 24 * 60 * 60,
+        miss_ttl: 60 * 60,
+    };
+    try!(get_usize(table, "ttl", &mut cache.ttl));
+    try!(get_usize(table, "miss_ttl", &mut cache.miss_ttl));
+    Ok(cache)
+}
+
+fn load_library(table: &toml::Table) -> Result<LibraryConfig, ConfigError> {
+    let dir = try!(try!(get_opt_str(table, "dir"))
+        .ok_or(invalid("library.dir", "given")));
+    Ok(LibraryConfig { dir: dir })
+}
+
+fn load_http(table: &toml::Table) -> Result<HttpConfig, ConfigError> {
+    let mut http = HttpConfig {
+        bind: "0.0.0.0:2795".to_string(),
+        url: String::new(),
+    };
+    try!(get_str(table, "bind", &mut http.bind));
+    try!(get_str(table, "url", &mut http.url));
+    if http.url.is_empty() {
+        // clients can't reach a wildcard address, so there's no guessing
+        // the url for one
+        if binds_all_addresses(&http.bind) {
+            return Err(invalid("http.url", "given when http.bind is a wildcard address"));
+        }
+        http.url = format!("http://{}", http.bind);
+    }
+    Ok(http)
+}
+
+fn binds_all_addresses(bind: &str) -> bool {
+    match bind.parse::<SocketAddr>() {
+        Ok(addr) => {
+            let ip = addr.ip();
+            ip == IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)) || ip == IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0))
+        },
+        Err(_) => false,
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            bind: "0.0.0.0:2794".to_string(),
+            protocol: "rust-websocket".to_string(),
+            log_level: LogLevelFilter::Info,
+            max_frame_len: 64 * 1024,
+            outbound_queue_len: 256,
+            overflow_policy: OverflowPolicy::DropOldest,
+            dynamic_rooms: true,
+            max_rooms: 100,
+            empty_room_ttl: 10 * 60,
+            auth: AuthConfig::Disabled,
+            providers: default_providers(),
+            media_cache: None,
+            library: None,
+            http: None,
+            data_dir: None,
+            max_playlists: 25,
+            max_playlist_len: 500,
+            room_defaults: RoomConfig::defaults(),
+            rooms: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(String),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Io(ref err) => write!(f, "unable to read config: {}", err),
+            ConfigError::Parse(ref msg) => write!(f, "unable to parse config: {}", msg),
+            ConfigError::Invalid(ref msg) => write!(f, "invalid config: {}", msg),
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> ConfigError {
+        ConfigError::Io(err)
+    }
+}
+
+fn invalid(key: &str, expected: &str) -> ConfigError {
+    ConfigError::Invalid(format!("{} must be {}", key, expected))
+}
+
+fn get_str(table: &toml::Table, key: &str, out: &mut String) -> Result<(), ConfigError> {
+    if let Some(value) = table.get(key) {
+        *out = try!(value.as_str().ok_or(invalid(key, "a string"))).to_string();
+    }
+    Ok(())
+}
+
+fn get_bool(table: &toml::Table, key: &str, out: &mut bool) -> Result<(), ConfigError> {
+    if let Some(value) = table.get(key) {
+        *out = try!(value.as_bool().ok_or(invalid(key, "a boolean")));
+    }
+    Ok(())
+}
+
+fn get_usize(table: &toml::Table, key: &str, out: &mut usize) -> Result<(), ConfigError> {
+    if let Some(value) = table.get(key) {
+        let val = try!(value.as_integer().ok_or(invalid(key, "an integer")));
+        if val < 0 {
+            return Err(invalid(key, "positive"));
+        }
+        *out = val as usize;
+    }
+    Ok(())
+}
+
+const ROLE_NAMES: &'static str =
+    "one of guest, user, resident_dj, bouncer, manager, co_host or host";
+
+fn get_role(table: &toml::Table, key: &str, out: &mut Role) -> Result<(), ConfigError> {
+    if let Some(value) = table.get(key) {
+        let name = try!(value.as_str().ok_or(invalid(key, ROLE_NAMES)));
+        *out = try!(Role::from_name(name).map_err(|_| invalid(key, ROLE_NAMES)));
+    }
+    Ok(())
+}
+
+fn get_table<'a>(table: &'a toml::Table, key: &str) -> Result<Option<&'a toml::Table>, ConfigError> {
+    match table.get(key) {
+        Some(value) => Ok(Some(try!(value.as_table().ok_or(invalid(key, "a table"))))),
+        None => Ok(None),
+    }
+}
+
+fn load_room(table: &toml::Table, room: &mut RoomConfig) -> Result<(), ConfigError> {
+    try!(get_str(table, "slug", &mut room.slug));
+    try!(get_role(table, "default_role", &mut room.default_role));
+    try!(get_usize(table, "max_waitlist", &mut room.max_waitlist));
+    try!(get_usize(table, "max_tracks_per_dj", &mut room.max_tracks_per_dj));
+    try!(get_usize(table, "meh_skip_percent", &mut room.meh_skip_percent));
+    try!(get_usize(table, "history_len", &mut room.history_len));
+    try!(get_usize(table, "max_message_len", &mut room.max_message_len));
+    try!(get_usize(table, "slow_mode", &mut room.slow_mode));
+    try!(get_usize(table, "flood_strikes", &mut room.flood_strikes));
+    try!(get_usize(table, "flood_mute", &mut room.flood_mute));
+    try!(get_bool(table, "limit_by_address", &mut room.limit_by_address));
+    try!(get_usize(table, "max_mute", &mut room.max_mute));
+    try!(get_usize(table, "max_ban", &mut room.max_ban));
+    try!(get_usize(table, "max_nick_len", &mut room.max_nick_len));
+    try!(get_bool(table, "require_auth", &mut room.require_auth));
+    try!(get_usize(table, "resume_grace", &mut room.resume_grace));
+    try!(get_usize(table, "max_duration", &mut room.max_duration));
+    if room.default_role == Role::Guest {
+        return Err(invalid("default_role", "above guest"));
+    }
+
+    if let Some(roles) = try!(get_table(table, "roles")) {
+        for nick in roles.keys() {
+            let mut role = Role::Guest;
+            try!(get_role(roles, nick, &mut role));
+            room.roles.insert(nick.clone(), role);
+        }
+    }
+    if let Some(limits) = try!(get_table(table, "rate_limits")) {
+        for kind in limits.keys() {
+            if !policy::is_request_kind(kind) {
+                return Err(ConfigError::Invalid(format!("no such request as {:?}", kind)));
+            }
+            let limit = try!(try!(get_table(limits, kind)).ok_or(invalid(kind, "a table")));
+            let (mut burst, mut interval) = (0, 0);
+            try!(get_usize(limit, "burst", &mut burst));
+            try!(get_usize(limit, "interval", &mut interval));
+            if burst == 0 || interval == 0 {
+                // a limit without a burst or interval isn't a limit
+                room.rate_limits.remove(kind);
+            } else {
+                room.rate_limits.insert(kind.clone(), RateLimit::new(burst as u64, interval as u64));
+            }
+        }
+    }
+    if let Some(permissions) = try!(get_table(table, "permissions")) {
+        for kind in permissions.keys() {
+            let mut role = Role::Guest;
+            try!(get_role(permissions, kind, &mut role));
+            if room.permissions.set(kind, role).is_err() {
+                return Err(ConfigError::Invalid(format!("no such request as {:?}", kind)));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn get_opt_str(table: &toml::Table, key: &str) -> Result<Option<String>, ConfigError> {
+    let mut value = String::new();
+    try!(get_str(table, key, &mut value));
+    Ok(if value.is_empty() { None } else { Some(value) })
+}
+
+fn load_auth(table: &toml::Table) -> Result<AuthConfig, ConfigError> {
+    let mut kind = String::new();
+    try!(get_str(table, "kind", &mut kind));
+    match &kind[..] {
+        "" | "disabled" => Ok(AuthConfig::Disabled),
+        "hmac" => {
+            let secret = try!(try!(get_opt_str(table, "secret"))
+                .ok_or(invalid("auth.secret", "given for hmac authentication")));
+            Ok(AuthConfig::Hmac { secret: secret })
+        },
+        "introspection" => {
+            let url = try!(try!(get_opt_str(table, "introspection_url"))
+                .ok_or(invalid("auth.introspection_url", "given for introspection")));
+            Ok(AuthConfig::Introspection {
+                url: url,
+                client_id: try!(get_opt_str(table, "client_id")),
+                client_secret: try!(get_opt_str(table, "client_secret")),
+            })
+        },
+        _ => Err(invalid("auth.kind", "one of disabled, hmac or introspection")),
+    }
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Config, ConfigError> {
+        let mut text = String::new();
+        try!(try!(File::open(path)).read_to_string(&mut text));
+        Config::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Config, ConfigError> {
+        let mut parser = toml::Parser::new(text);
+        let table = match parser.parse() {
+            Some(table) => table,
+            None => {
+                let msg = parser.errors.iter()
+                    .map(|err| {
+                        let (line, col) = parser.to_linecol(err.lo);
+                        format!("{}:{}: {}", line + 1, col + 1, err.desc)
+                    })
+                    .collect::<Vec<_>>()
+                    .join("; ");
+                return Err(ConfigError::Parse(msg));
+            }
+        };
+
+        let mut config = Config::default();
+        try!(get_str(&table, "bind", &mut config.bind));
+        try!(get_str(&table, "protocol", &mut config.protocol));
+        try!(get_usize(&table, "max_frame_len", &mut config.max_frame_len));
+        try!(get_usize(&table, "outbound_queue_len", &mut config.outbound_queue_len));
+        try!(get_bool(&table, "dynamic_rooms", &mut config.dynamic_rooms));
+        try!(get_usize(&table, "max_rooms", &mut config.max_rooms));
+        try!(get_usize(&table, "empty_room_ttl", &mut config.empty_room_ttl));
+        config.data_dir = try!(get_opt_str(&table, "data_dir"));
+        try!(get_usize(&table, "max_playlists", &mut config.max_playlists));
+        try!(get_usize(&table, "max_playlist_len", &mut config.max_playlist_len));
+
+        let mut overflow_policy = String::new();
+        try!(get_str(&table, "overflow_policy", &mut overflow_policy));
+        if !overflow_policy.is_empty() {
+            config.overflow_policy = try!(OverflowPolicy::from_name(&overflow_policy)
+                .map_err(|_| invalid("overflow_policy", "drop_oldest or disconnect")));
+        }
+        if config.outbound_queue_len == 0 {
+            return Err(invalid("outbound_queue_len", "at least 1"));
+        }
+
+        let mut log_level = String::new();
+        try!(get_str(&table, "log_level", &mut log_level));
+        if !log_level.is_empty() {
+            try!(config.set_log_level(&log_level));
+        }
+
+        if let Some(value) = table.get("auth") {
+            let auth = try!(value.as_table().ok_or(invalid("auth", "a table")));
+            config.auth = try!(load_auth(auth));
+        }
+
+        if let Some(http) = try!(get_table(&table, "http")) {
+            config.http = Some(try!(load_http(http)));
+        }
+
+        if let Some(library) = try!(get_table(&table, "library")) {
+            if config.http.is_none() {
+                return Err(invalid("http", "given to stream the library"));
+            }
+            config.library = Some(try!(load_library(library)));
+            // a library is played from unless the providers table says not
+            config.providers.insert(ProviderKind::Library, ProviderConfig::Library);
+        }
+
+        if let Some(providers) = try!(get_table(&table, "providers")) {
+            for name in providers.keys() {
+                let kind = try!(ProviderKind::from_name(name)
+                    .map_err(|_| invalid("providers", "keyed by youtube, soundcloud, url or library")));
+                let provider = try!(try!(get_table(providers, name)).ok_or(invalid(name, "a table")));
+                match try!(load_provider(provider)) {
+                    Some(provider) => config.providers.insert(kind, provider),
+                    None => config.providers.remove(&kind),
+                };
+            }
+        }
+
+        match config.providers.get(&ProviderKind::Library) {
+            Some(&ProviderConfig::Library) if config.library.is_none() => {
+                return Err(invalid("library.dir", "given to play from the library"));
+            },
+            _ => (),
+        }
+
+        if let Some(cache) = try!(get_table(&table, "media_cache")) {
+            config.media_cache = Some(try!(load_media_cache(cache)));
+        }
+
+        if let Some(value) = table.get("room_defaults") {
+            let defaults = try!(value.as_table().ok_or(invalid("room_defaults", "a table")));
+            try!(load_room(defaults, &mut config.room_defaults));
+        }
+
+        if let Some(value) = table.get("rooms") {
+            let rooms = try!(value.as_slice().ok_or(invalid("rooms", "an array of tables")));
+            for value in rooms.iter() {
+                let table = try!(value.as_table().ok_or(invalid("rooms", "an array of tables")));
+                let mut room = config.room_defaults.clone();
+                try!(load_room(table, &mut room));
+                try!(config.add_room(room));
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn set_log_level(&mut self, level: &str) -> Result<(), ConfigError> {
+        self.log_level = try!(level.parse()
+            .map_err(|_| invalid("log_level", "one of off, error, warn, info, debug or trace")));
+        Ok(())
+    }
+
+    pub fn add_room(&mut self, mut room: RoomConfig) -> Result<(), ConfigError> {
+        room.slug = match rooms::normalize_slug(&room.slug) {
+            Some(slug) => slug,
+            None => return Err(invalid("slug", "given for every room, using only a-z, 0-9, - and _")),
+        };
+        if self.rooms.iter().any(|r| r.slug == room.slug) {
+            return Err(ConfigError::Invalid(format!("room {:?} is listed twice", room.slug)));
+        }
+        self.rooms.push(room);
+        Ok(())
+    }
+
+    /// The settings for the room `slug`, or None if it isn't listed and
+    /// rooms can't be created on demand.
+    pub fn room(&self, slug: &str) -> Option<RoomConfig> {
+        match self.rooms.iter().find(|r| r.slug == slug) {
+            Some(room) => Some(room.clone()),
+            None if self.dynamic_rooms => Some(self.room_defaults.for_slug(slug)),
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use api::Role;
+    use super::*;
+
+    fn invalid(text: &str) -> bool {
+        match Config::parse(text) {
+            Err(ConfigError::Invalid(_)) => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn an_empty_file_gives_the_defaults() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(config.bind, "0.0.0.0:2794");
+        assert!(config.dynamic_rooms);
+        assert_eq!(config.max_rooms, 100);
+        assert_eq!(config.empty_room_ttl, 600);
+        assert!(config.http.is_none());
+        assert!(config.rooms.is_empty());
+
+        let room = config.room("lounge").unwrap();
+        assert_eq!(room.slug, "lounge");
+        assert_eq!(room.default_role, Role::User);
+        assert!(!room.limit_by_address);
+        assert_eq!(room.rate_limits["message"].burst, 5);
+    }
+
+    #[test]
+    fn listed_rooms_start_from_the_room_defaults() {
+        let config = Config::parse(r#"
+            dynamic_rooms = false
+            max_rooms = 3
+
+            [room_defaults]
+            slow_mode = 5
+
+            [[rooms]]
+            slug = "lounge"
+            max_waitlist = 10
+
+            [rooms.rate_limits.message]
+            burst = 0
+            interval = 0
+        "#).unwrap();
+        assert_eq!(config.max_rooms, 3);
+        let room = config.room("lounge").unwrap();
+        assert_eq!(room.slow_mode, 5);
+        assert_eq!(room.max_waitlist, 10);
+        assert!(!room.rate_limits.contains_key("message"));
+        assert!(config.room("elsewhere").is_none());
+    }
+
+    #[test]
+    fn mistakes_are_refused() {
+        assert!(invalid("[room_defaults]\ndefault_role = \"guest\""));
+        assert!(invalid("[room_defaults.rate_limits.shout]\nburst = 1\ninterval = 1000"));
+        assert!(invalid("[[rooms]]\nmax_waitlist = 10"));
+        assert!(invalid("max_rooms = \"lots\""));
+        match Config::parse("bind = ") {
+            Err(ConfigError::Parse(_)) => (),
+            other => panic!("expected Parse, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn http_needs_a_url_on_wildcard_addresses() {
+        assert!(invalid("[http]\nbind = \"0.0.0.0:2795\""));
+        let config = Config::parse("[http]\nbind = \"127.0.0.1:2795\"").unwrap();
+        assert_eq!(config.http.unwrap().url, "http://127.0.0.1:2795");
+        let config = Config::parse("[http]\nurl = \"http://radio.example.com\"").unwrap();
+        assert_eq!(config.http.unwrap().url, "http://radio.example.com");
+    }
+}
@@ -0,0 +1,72 @@
+//! Just enough of application/x-www-form-urlencoded for query strings and
+//! the odd POST body.
+
+/// Percent-encodes everything but unreserved characters.
+pub fn encode(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for &byte in value.as_bytes().iter() {
+        match byte {
+            b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(byte as char)
+            },
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'...b'9' => Some(byte - b'0'),
+        b'a'...b'f' => Some(byte - b'a' + 10),
+        b'A'...b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Undoes `encode`, also treating `+` as a space. Malformed escapes are
+/// passed through untouched.
+pub fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let byte = bytes[idx];
+        idx += 1;
+        if byte == b'+' {
+            out.push(b' ');
+            continue;
+        }
+        if byte == b'%' && idx + 2 <= bytes.len() {
+            if let (Some(hi), Some(lo)) = (hex_value(bytes[idx]), hex_value(bytes[idx + 1])) {
+                out.push(hi << 4 | lo);
+                idx += 2;
+                continue;
+            }
+        }
+        out.push(byte);
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Splits `a=1&b=2` into decoded pairs.
+pub fn parse(query: &str) -> Vec<(String, String)> {
+    query.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.find('=') {
+            Some(idx) => (decode(&pair[..idx]), decode(&pair[idx + 1..])),
+            None => (decode(pair), String::new()),
+        })
+        .collect()
+}
+
+/// Finds the value of `key` in the query string of a request path.
+pub fn query_param(path: &str, key: &str) -> Option<String> {
+    let query = match path.find('?') {
+        Some(idx) => &path[idx + 1..],
+        None => return None,
+    };
+    parse(query).into_iter()
+        .find(|&(ref k, _)| k == key)
+        .map(|(_, v)| v)
+}
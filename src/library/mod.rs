@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use api::MediaInfo;
+use form;
+use media::{MediaError, Provider};
+use self::tags::Format;
+
+pub mod stream;
+mod tags;
+
+/// An audio file in the library.
+pub struct Track {
+    pub path: PathBuf,
+    pub format: Format,
+    // size of the file in bytes
+    pub len: u64,
+    pub info: MediaInfo,
+}
+
+/// The audio files in a directory, found when the server starts. Tracks
+/// are named by their path within the directory, with `/` between
+/// components whatever the platform.
+pub struct Library {
+    tracks: HashMap<String, Track>,
+}
+
+impl Library {
+    /// Indexes the audio files under `dir`, which will be streamed from
+    /// under `url`. Files and directories which can't be read are left out,
+    /// as are links to directories.
+    pub fn index(dir: &Path, url: &str) -> io::Result<Library> {
+        let mut library = Library { tracks: HashMap::new() };
+        try!(library.index_dir(dir, "", url.trim_right_matches('/')));
+        info!("indexed {} tracks in {}", library.tracks.len(), dir.display());
+        Ok(library)
+    }
+
+    fn index_dir(&mut self, dir: &Path, prefix: &str, url: &str) -> io::Result<()> {
+        for entry in try!(fs::read_dir(dir)) {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    warn!("skipping an entry in {}: {}", dir.display(), err);
+                    continue;
+                }
+            };
+            let path = entry.path();
+            let name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                Err(_) => {
+                    warn!("skipping {}: name isn't unicode", path.display());
+                    continue;
+                }
+            };
+            if name.starts_with('.') {
+                continue;
+            }
+            let id = format!("{}{}", prefix, name);
+            // links to files are followed, but not links to directories,
+            // which could lead back up the tree
+            let metadata = match fs::symlink_metadata(&path) {
+                Ok(ref metadata) if metadata.file_type().is_symlink() => match fs::metadata(&path) {
+                    Ok(ref metadata) if metadata.is_dir() => {
+                        warn!("skipping {}: links to directories aren't followed", path.display());
+                        continue;
+                    },
+                    other => other,
+                },
+                other => other,
+            };
+            let metadata = match metadata {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    warn!("skipping {}: {}", path.display(), err);
+                    continue;
+                }
+            };
+            if metadata.is_dir() {
+                if let Err(err) = self.index_dir(&path, &format!("{}/", id), url) {
+                    warn!("skipping {}: {}", path.display(), err);
+                }
+                continue;
+            }
+            let format = match path.extension().and_then(|ext| ext.to_str()).and_then(Format::from_extension) {
+                Some(format) => format,
+                None => continue,
+            };
+            let tags = match tags::read(&path, format) {
+                Ok(tags) => tags,
+                Err(err) => {
+                    warn!("skipping {}: {}", path.display(), err);
+                    continue;
+                }
+            };
+            if tags.duration == 0 {
+                warn!("skipping {}: no audio", path.display());
+                continue;
+            }
+            let title = tags.title.unwrap_or_else(|| {
+                path.file_stem().map_or(name.clone(), |stem| stem.to_string_lossy().into_owned())
+            });
+            let info = MediaInfo {
+                title: title,
+                artist: tags.artist,
+                duration: tags.duration,
+                thumbnail: None,
+                stream_url: Some(format!("{}/library/{}", url, form::encode(&id))),
+            };
+            self.tracks.insert(id, Track {
+                path: path,
+                format: format,
+                len: metadata.len(),
+                info: info,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Track> {
+        self.tracks.get(id)
+    }
+}
+
+impl Provider for Library {
+    fn resolve(&self, id: &str) -> Result<MediaInfo, MediaError> {
+        match self.get(id) {
+            Some(track) => Ok(track.info.clone()),
+            None => Err(MediaError::NotFound(format!("no {} in the library", id))),
+        }
+    }
+}
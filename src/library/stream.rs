@@ -0,0 +1,109 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use hyper::header::{AcceptRanges, ByteRangeSpec, ContentLength, ContentRange, ContentRangeSpec,
+                    ContentType, Range, RangeUnit};
+use hyper::server::Response;
+use hyper::status::StatusCode;
+
+use super::Track;
+
+/// Works out which bytes of a file `len` bytes long the client asked for,
+/// as the first and last byte. None means the whole file, and Err that
+/// the range can't be satisfied. Requests for several ranges get the
+/// whole file.
+fn requested_range(range: Option<&Range>, len: u64) -> Result<Option<(u64, u64)>, ()> {
+    let spec = match range {
+        Some(&Range::Bytes(ref specs)) if specs.len() == 1 => &specs[0],
+        _ => return Ok(None),
+    };
+    let (first, last) = match *spec {
+        ByteRangeSpec::FromTo(first, last) => (first, if len <= last { len.saturating_sub(1) } else { last }),
+        ByteRangeSpec::AllFrom(first) => (first, len.saturating_sub(1)),
+        ByteRangeSpec::Last(0) => return Err(()),
+        ByteRangeSpec::Last(count) => (len.saturating_sub(count), len.saturating_sub(1)),
+    };
+    if len <= first || last < first {
+        return Err(());
+    }
+    Ok(Some((first, last)))
+}
+
+/// Sends the file of `track`, or the part of it in `range`, so that
+/// clients can start playing partway through.
+pub fn send(track: &Track, range: Option<&Range>, mut res: Response) -> io::Result<()> {
+    let mut file = try!(File::open(&track.path));
+    res.headers_mut().set(AcceptRanges(vec![RangeUnit::Bytes]));
+    res.headers_mut().set(ContentType(track.format.mime_type().parse().unwrap()));
+    let (first, last) = match requested_range(range, track.len) {
+        Ok(Some((first, last))) => {
+            *res.status_mut() = StatusCode::PartialContent;
+            res.headers_mut().set(ContentRange(ContentRangeSpec::Bytes {
+                range: Some((first, last)),
+                instance_length: Some(track.len),
+            }));
+            (first, last)
+        },
+        Ok(None) => (0, track.len.saturating_sub(1)),
+        Err(()) => {
+            *res.status_mut() = StatusCode::RangeNotSatisfiable;
+            res.headers_mut().set(ContentRange(ContentRangeSpec::Bytes {
+                range: None,
+                instance_length: Some(track.len),
+            }));
+            return res.send(b"");
+        },
+    };
+    let count = if track.len == 0 { 0 } else { last - first + 1 };
+    res.headers_mut().set(ContentLength(count));
+    try!(file.seek(SeekFrom::Start(first)));
+    let mut res = try!(res.start());
+    try!(io::copy(&mut file.take(count), &mut res));
+    res.end()
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::{ByteRangeSpec, Range};
+
+    use super::requested_range;
+
+    fn range(spec: ByteRangeSpec) -> Range {
+        Range::Bytes(vec![spec])
+    }
+
+    #[test]
+    fn ranges_are_clamped_to_the_file() {
+        assert_eq!(requested_range(None, 1000), Ok(None));
+        assert_eq!(requested_range(Some(&range(ByteRangeSpec::FromTo(0, 499))), 1000), Ok(Some((0, 499))));
+        assert_eq!(requested_range(Some(&range(ByteRangeSpec::FromTo(900, 2000))), 1000), Ok(Some((900, 999))));
+        assert_eq!(requested_range(Some(&range(ByteRangeSpec::AllFrom(900))), 1000), Ok(Some((900, 999))));
+        assert_eq!(requested_range(Some(&range(ByteRangeSpec::Last(100))), 1000), Ok(Some((900, 999))));
+        assert_eq!(requested_range(Some(&range(ByteRangeSpec::Last(5000))), 1000), Ok(Some((0, 999))));
+    }
+
+    #[test]
+    fn several_ranges_get_the_whole_file() {
+        let ranges = Range::Bytes(vec![ByteRangeSpec::FromTo(0, 9), ByteRangeSpec::FromTo(20, 29)]);
+        assert_eq!(requested_range(Some(&ranges), 1000), Ok(None));
+    }
+
+    #[test]
+    fn no_bytes_from_the_end_is_unsatisfiable() {
+        assert_eq!(requested_range(Some(&range(ByteRangeSpec::Last(0))), 1000), Err(()));
+    }
+
+    #[test]
+    fn ranges_starting_past_the_end_are_unsatisfiable() {
+        assert_eq!(requested_range(Some(&range(ByteRangeSpec::FromTo(1000, 1999))), 1000), Err(()));
+        assert_eq!(requested_range(Some(&range(ByteRangeSpec::AllFrom(1500))), 1000), Err(()));
+        assert_eq!(requested_range(Some(&range(ByteRangeSpec::FromTo(500, 400))), 1000), Err(()));
+    }
+
+    #[test]
+    fn no_range_of_an_empty_file_is_satisfiable() {
+        assert_eq!(requested_range(None, 0), Ok(None));
+        assert_eq!(requested_range(Some(&range(ByteRangeSpec::AllFrom(0))), 0), Err(()));
+        assert_eq!(requested_range(Some(&range(ByteRangeSpec::FromTo(0, 99))), 0), Err(()));
+        assert_eq!(requested_range(Some(&range(ByteRangeSpec::Last(100))), 0), Err(()));
+    }
+}
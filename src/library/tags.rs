@@ -0,0 +1,578 @@
+//! Just enough of WAV, FLAC and MP3 to find out how long a file plays for
+//! and what it's called.
+
+use std::cmp;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    Wav,
+    Flac,
+    Mp3,
+}
+
+impl Format {
+    /// The format of a file with the extension `ext`, if it's one we read.
+    pub fn from_extension(ext: &str) -> Option<Format> {
+        match &ext.to_lowercase()[..] {
+            "wav" => Some(Format::Wav),
+            "flac" => Some(Format::Flac),
+            "mp3" => Some(Format::Mp3),
+            _ => None,
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match *self {
+            Format::Wav => "audio/wav",
+            Format::Flac => "audio/flac",
+            Format::Mp3 => "audio/mpeg",
+        }
+    }
+}
+
+/// What the headers of a file say about it.
+#[derive(Debug, Default)]
+pub struct Tags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    // in milliseconds
+    pub duration: u64,
+}
+
+// largest chunk of a WAVE file read into memory; bigger ones are skipped
+const MAX_CHUNK_LEN: u64 = 1024 * 1024;
+
+fn malformed(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Fills `buf`, failing if the file ends first.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<()> {
+    let mut done = 0;
+    while done < buf.len() {
+        match try!(reader.read(&mut buf[done..])) {
+            0 => return Err(malformed("unexpected end of file")),
+            n => done += n,
+        }
+    }
+    Ok(())
+}
+
+fn read_vec<R: Read>(reader: &mut R, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; len];
+    try!(read_full(reader, &mut buf));
+    Ok(buf)
+}
+
+fn le32(b: &[u8]) -> u32 {
+    b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24
+}
+
+fn be32(b: &[u8]) -> u32 {
+    (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32
+}
+
+fn be24(b: &[u8]) -> u32 {
+    (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32
+}
+
+// ID3v2 sizes use seven bits of each byte
+fn syncsafe(b: &[u8]) -> u32 {
+    (b[0] as u32 & 0x7f) << 21 | (b[1] as u32 & 0x7f) << 14 | (b[2] as u32 & 0x7f) << 7 | b[3] as u32 & 0x7f
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+fn utf16(bytes: &[u8], big_endian: bool) -> String {
+    let units: Vec<u16> = bytes.chunks(2)
+        .filter(|pair| pair.len() == 2)
+        .map(|pair| if big_endian {
+            (pair[0] as u16) << 8 | pair[1] as u16
+        } else {
+            (pair[1] as u16) << 8 | pair[0] as u16
+        })
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// Tidies up a tag value, treating empty ones as missing.
+fn clean(value: String) -> Option<String> {
+    let value = value.trim_matches(|c: char| c == '\0' || c.is_whitespace()).to_string();
+    if value.is_empty() { None } else { Some(value) }
+}
+
+/// Reads the headers of the file at `path`, which is in `format`.
+pub fn read(path: &Path, format: Format) -> io::Result<Tags> {
+    let mut file = try!(File::open(path));
+    match format {
+        Format::Wav => read_wav(&mut file),
+        Format::Flac => read_flac(&mut file),
+        Format::Mp3 => {
+            let len = try!(file.metadata()).len();
+            read_mp3(&mut file, len)
+        },
+    }
+}
+
+fn read_wav<R: Read + Seek>(reader: &mut R) -> io::Result<Tags> {
+    let header = try!(read_vec(reader, 12));
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return Err(malformed("not a RIFF WAVE file"));
+    }
+    let mut tags = Tags::default();
+    let mut byte_rate = 0;
+    let mut data_len = None;
+    loop {
+        let mut chunk = [0u8; 8];
+        // a file may end after any chunk
+        if read_full(reader, &mut chunk).is_err() {
+            break;
+        }
+        let size = le32(&chunk[4..8]) as u64;
+        // chunks are padded to an even length
+        let padded = size + (size & 1);
+        match &chunk[0..4] {
+            b"fmt " if 16 <= size && padded <= MAX_CHUNK_LEN => {
+                let fmt = try!(read_vec(reader, padded as usize));
+                byte_rate = le32(&fmt[8..12]) as u64;
+            },
+            b"data" => {
+                data_len = Some(size);
+                try!(reader.seek(SeekFrom::Current(padded as i64)));
+            },
+            b"LIST" if padded <= MAX_CHUNK_LEN => {
+                let list = try!(read_vec(reader, padded as usize));
+                if 4 <= list.len() && &list[0..4] == b"INFO" {
+                    read_riff_info(&list[4..], &mut tags);
+                }
+            },
+            _ => {
+                try!(reader.seek(SeekFrom::Current(padded as i64)));
+            },
+        }
+    }
+    match data_len {
+        Some(len) if 0 < byte_rate => tags.duration = len * 1000 / byte_rate,
+        _ => return Err(malformed("no audio in WAVE file")),
+    }
+    Ok(tags)
+}
+
+fn read_riff_info(mut info: &[u8], tags: &mut Tags) {
+    while 8 <= info.len() {
+        let size = le32(&info[4..8]) as usize;
+        let end = 8 + size;
+        if info.len() < end {
+            return;
+        }
+        let value = clean(String::from_utf8_lossy(&info[8..end]).into_owned());
+        match &info[0..4] {
+            b"INAM" => tags.title = value,
+            b"IART" => tags.artist = value,
+            _ => (),
+        }
+        info = &info[cmp::min(end + (size & 1), info.len())..];
+    }
+}
+
+fn read_flac<R: Read + Seek>(reader: &mut R) -> io::Result<Tags> {
+    let magic = try!(read_vec(reader, 4));
+    if &magic[..] != b"fLaC" {
+        return Err(malformed("not a FLAC file"));
+    }
+    let mut tags = Tags::default();
+    let mut found_info = false;
+    loop {
+        let header = try!(read_vec(reader, 4));
+        let last = header[0] & 0x80 != 0;
+        let len = be24(&header[1..4]) as usize;
+        match header[0] & 0x7f {
+            // STREAMINFO
+            0 if 18 <= len => {
+                let info = try!(read_vec(reader, len));
+                let sample_rate = (info[10] as u64) << 12 | (info[11] as u64) << 4 | (info[12] as u64) >> 4;
+                let samples = (info[13] as u64 & 0x0f) << 32 | be32(&info[14..18]) as u64;
+                if sample_rate == 0 || samples == 0 {
+                    return Err(malformed("FLAC file doesn't give its length"));
+                }
+                tags.duration = samples * 1000 / sample_rate;
+                found_info = true;
+            },
+            // VORBIS_COMMENT
+            4 => {
+                let comments = try!(read_vec(reader, len));
+                read_vorbis_comments(&comments, &mut tags);
+            },
+            _ => {
+                try!(reader.seek(SeekFrom::Current(len as i64)));
+            },
+        }
+        if last {
+            break;
+        }
+    }
+    if !found_info {
+        return Err(malformed("FLAC file has no STREAMINFO"));
+    }
+    Ok(tags)
+}
+
+// a little-endian length comes before each string, and before the count of
+// comments which follows the vendor string
+fn next_len(block: &[u8], pos: &mut usize) -> Option<usize> {
+    if block.len() < *pos + 4 {
+        return None;
+    }
+    let len = le32(&block[*pos..*pos + 4]) as usize;
+    *pos += 4;
+    Some(len)
+}
+
+fn read_vorbis_comments(block: &[u8], tags: &mut Tags) {
+    let mut pos = 0;
+    let vendor = match next_len(block, &mut pos) {
+        Some(len) => len,
+        None => return,
+    };
+    pos += vendor;
+    let count = match next_len(block, &mut pos) {
+        Some(count) => count,
+        None => return,
+    };
+    for _ in 0..count {
+        let len = match next_len(block, &mut pos) {
+            Some(len) if pos + len <= block.len() => len,
+            _ => return,
+        };
+        let comment = String::from_utf8_lossy(&block[pos..pos + len]).into_owned();
+        pos += len;
+        if let Some(idx) = comment.find('=') {
+            let value = clean(comment[idx + 1..].to_string());
+            match &comment[..idx].to_uppercase()[..] {
+                "TITLE" => tags.title = value,
+                "ARTIST" => tags.artist = value,
+                _ => (),
+            }
+        }
+    }
+}
+
+fn read_mp3<R: Read + Seek>(reader: &mut R, file_len: u64) -> io::Result<Tags> {
+    let mut tags = Tags::default();
+    let mut audio_start = 0;
+
+    let header = try!(read_vec(reader, 10));
+    if &header[0..3] == b"ID3" {
+        let size = syncsafe(&header[6..10]) as u64;
+        if file_len < 10 + size {
+            return Err(malformed("ID3 tag runs past the end of the file"));
+        }
+        let tag = try!(read_vec(reader, size as usize));
+        read_id3v2(header[3], header[5], &tag, &mut tags);
+        audio_start = 10 + size;
+        // ID3v2.4 tags may have a footer
+        if header[5] & 0x10 != 0 {
+            audio_start += 10;
+        }
+    }
+
+    let mut audio_end = file_len;
+    if audio_start + 128 <= file_len {
+        try!(reader.seek(SeekFrom::Start(file_len - 128)));
+        let v1 = try!(read_vec(reader, 128));
+        if &v1[0..3] == b"TAG" {
+            audio_end -= 128;
+            if tags.title.is_none() {
+                tags.title = clean(latin1(&v1[3..33]));
+            }
+            if tags.artist.is_none() {
+                tags.artist = clean(latin1(&v1[33..63]));
+            }
+        }
+    }
+
+    // the first frame is usually right after the tag, but may be preceded
+    // by junk
+    try!(reader.seek(SeekFrom::Start(audio_start)));
+    let mut buf = Vec::new();
+    try!(reader.take(64 * 1024).read_to_end(&mut buf));
+    let mut idx = 0;
+    while idx + 4 <= buf.len() {
+        if let Some(frame) = FrameHeader::parse(&buf[idx..idx + 4]) {
+            let audio_len = audio_end.saturating_sub(audio_start + idx as u64);
+            tags.duration = match frame.xing_frames(&buf[idx..]) {
+                Some(frames) => frames * frame.samples as u64 * 1000 / frame.sample_rate as u64,
+                // constant bitrate: kbps are bits per millisecond
+                None => audio_len * 8 / frame.bitrate as u64,
+            };
+            return Ok(tags);
+        }
+        idx += 1;
+    }
+    Err(malformed("no MPEG audio frames found"))
+}
+
+fn read_id3v2(version: u8, flags: u8, tag: &[u8], tags: &mut Tags) {
+    // ID3v2.2 has three-letter frame ids and three-byte sizes
+    let (id_len, size_len) = if version < 3 { (3, 3) } else { (4, 4) };
+    let header_len = if version < 3 { 6 } else { 10 };
+    let mut pos = 0;
+    if flags & 0x40 != 0 && 3 <= version && 4 <= tag.len() {
+        // the extended header's size counts itself in 2.4 but not in 2.3
+        pos = if version == 3 { (be32(&tag[0..4]) as usize).saturating_add(4) } else { syncsafe(&tag[0..4]) as usize };
+    }
+    while pos <= tag.len() && header_len <= tag.len() - pos {
+        let id = &tag[pos..pos + id_len];
+        if id[0] == 0 {
+            // padding
+            break;
+        }
+        let size_bytes = &tag[pos + id_len..pos + id_len + size_len];
+        let size = match version {
+            2 => be24(size_bytes),
+            3 => be32(size_bytes),
+            _ => syncsafe(size_bytes),
+        } as usize;
+        let start = pos + header_len;
+        if tag.len() - start < size {
+            break;
+        }
+        match id {
+            b"TIT2" | b"TT2" => tags.title = id3_text(&tag[start..start + size]),
+            b"TPE1" | b"TP1" => tags.artist = id3_text(&tag[start..start + size]),
+            _ => (),
+        }
+        pos = start + size;
+    }
+}
+
+fn id3_text(frame: &[u8]) -> Option<String> {
+    if frame.is_empty() {
+        return None;
+    }
+    let text = &frame[1..];
+    let value = match frame[0] {
+        0 => latin1(text),
+        // UTF-16 with a byte order mark
+        1 if 2 <= text.len() => utf16(&text[2..], text[0] == 0xfe),
+        2 => utf16(text, true),
+        3 => String::from_utf8_lossy(text).into_owned(),
+        _ => return None,
+    };
+    clean(value)
+}
+
+const BITRATES_V1: [[u32; 14]; 3] = [
+    [32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448],
+    [32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384],
+    [32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320],
+];
+
+const BITRATES_V2: [[u32; 14]; 2] = [
+    [32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256],
+    [8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+];
+
+/// The header of an MPEG audio frame.
+struct FrameHeader {
+    mpeg1: bool,
+    mono: bool,
+    // in kbps
+    bitrate: u32,
+    sample_rate: u32,
+    // samples per frame
+    samples: u32,
+}
+
+impl FrameHeader {
+    fn parse(b: &[u8]) -> Option<FrameHeader> {
+        if b[0] != 0xff || b[1] & 0xe0 != 0xe0 {
+            return None;
+        }
+        let version = (b[1] >> 3) & 3;
+        let layer = (b[1] >> 1) & 3;
+        let bitrate_idx = (b[2] >> 4) as usize;
+        let rate_idx = ((b[2] >> 2) & 3) as usize;
+        // reserved values, and free-format bitrates we can't time
+        if version == 1 || layer == 0 || bitrate_idx == 0 || bitrate_idx == 15 || rate_idx == 3 {
+            return None;
+        }
+        let mpeg1 = version == 3;
+        // layer bits count down: 3 is layer I
+        let layer = 4 - layer as usize;
+        let bitrate = if mpeg1 {
+            BITRATES_V1[layer - 1][bitrate_idx - 1]
+        } else {
+            BITRATES_V2[if layer == 1 { 0 } else { 1 }][bitrate_idx - 1]
+        };
+        let base_rate = [44100, 48000, 32000][rate_idx];
+        let sample_rate = match version {
+            3 => base_rate,
+            2 => base_rate / 2,
+            _ => base_rate / 4,
+        };
+        let samples = match layer {
+            1 => 384,
+            2 => 1152,
+            _ => if mpeg1 { 1152 } else { 576 },
+        };
+        Some(FrameHeader {
+            mpeg1: mpeg1,
+            mono: b[3] >> 6 == 3,
+            bitrate: bitrate,
+            sample_rate: sample_rate,
+            samples: samples,
+        })
+    }
+
+    /// The number of frames in the file, if `frame` (which starts with this
+    /// header) is a Xing or Info frame which gives it.
+    fn xing_frames(&self, frame: &[u8]) -> Option<u64> {
+        let side_info = match (self.mpeg1, self.mono) {
+            (true, false) => 32,
+            (true, true) => 17,
+            (false, false) => 17,
+            (false, true) => 9,
+        };
+        let pos = 4 + side_info;
+        if frame.len() < pos + 12 {
+            return None;
+        }
+        let magic = &frame[pos..pos + 4];
+        if magic != b"Xing" && magic != b"Info" {
+            return None;
+        }
+        let flags = be32(&frame[pos + 4..pos + 8]);
+        if flags & 1 == 0 {
+            return None;
+        }
+        Some(be32(&frame[pos + 8..pos + 12]) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor};
+
+    use super::{Tags, read_flac, read_id3v2, read_mp3, read_wav};
+
+    fn push_le32(buf: &mut Vec<u8>, n: u32) {
+        buf.extend(&[n as u8, (n >> 8) as u8, (n >> 16) as u8, (n >> 24) as u8]);
+    }
+
+    fn push_be32(buf: &mut Vec<u8>, n: u32) {
+        buf.extend(&[(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8]);
+    }
+
+    // a RIFF WAVE file of a second of CD audio, with the chunks given
+    fn wav(with_fmt: bool) -> Vec<u8> {
+        let mut chunks = Vec::new();
+        if with_fmt {
+            chunks.extend(b"fmt ");
+            push_le32(&mut chunks, 16);
+            // PCM, two channels, 44100Hz, 176400 bytes a second
+            chunks.extend(&[1, 0, 2, 0]);
+            push_le32(&mut chunks, 44100);
+            push_le32(&mut chunks, 176400);
+            chunks.extend(&[4, 0, 16, 0]);
+        }
+        chunks.extend(b"data");
+        push_le32(&mut chunks, 176400);
+        chunks.extend(vec![0; 176400]);
+
+        let mut file = b"RIFF".to_vec();
+        push_le32(&mut file, 4 + chunks.len() as u32);
+        file.extend(b"WAVE");
+        file.extend(chunks);
+        file
+    }
+
+    // an MPEG-1 layer III stream at 128kbps and 44100Hz, `len` bytes long,
+    // whose first frame is a Xing frame if `xing_frames` is given
+    fn mp3(len: usize, xing_frames: Option<u32>) -> Vec<u8> {
+        let mut file = vec![0xff, 0xfb, 0x90, 0x00];
+        file.extend(vec![0; 32]);
+        if let Some(frames) = xing_frames {
+            file.extend(b"Xing");
+            push_be32(&mut file, 1);
+            push_be32(&mut file, frames);
+        }
+        let rest = len - file.len();
+        file.extend(vec![0; rest]);
+        file
+    }
+
+    fn read_mp3_bytes(file: Vec<u8>) -> io::Result<Tags> {
+        let len = file.len() as u64;
+        read_mp3(&mut Cursor::new(file), len)
+    }
+
+    #[test]
+    fn wav_durations_come_from_the_byte_rate() {
+        let tags = read_wav(&mut Cursor::new(wav(true))).unwrap();
+        assert_eq!(tags.duration, 1000);
+    }
+
+    #[test]
+    fn wav_files_need_a_fmt_chunk() {
+        assert!(read_wav(&mut Cursor::new(wav(false))).is_err());
+    }
+
+    #[test]
+    fn truncated_headers_are_malformed() {
+        assert!(read_wav(&mut Cursor::new(b"RIFF\0\0".to_vec())).is_err());
+        assert!(read_flac(&mut Cursor::new(b"fLaC\0\0".to_vec())).is_err());
+        assert!(read_mp3_bytes(b"ID3\x03".to_vec()).is_err());
+        // a STREAMINFO block cut short
+        let mut flac = b"fLaC".to_vec();
+        flac.extend(&[0x80, 0, 0, 34]);
+        flac.extend(vec![0; 10]);
+        assert!(read_flac(&mut Cursor::new(flac)).is_err());
+    }
+
+    #[test]
+    fn id3_tags_longer_than_the_file_are_malformed() {
+        // claims a tag of 2^28 - 1 bytes
+        let mut file = b"ID3\x03\x00\x00\x7f\x7f\x7f\x7f".to_vec();
+        file.extend(mp3(1024, None));
+        assert!(read_mp3_bytes(file).is_err());
+    }
+
+    #[test]
+    fn oversized_id3_extended_headers_are_ignored() {
+        let mut tag = Vec::new();
+        push_be32(&mut tag, 0xffffffff);
+        tag.extend(b"TIT2\0\0\0\x06\0\0\x00Intro");
+        let mut tags = Tags::default();
+        read_id3v2(3, 0x40, &tag, &mut tags);
+        assert_eq!(tags.title, None);
+        read_id3v2(4, 0x40, &[0x7f, 0x7f, 0x7f, 0x7f], &mut tags);
+        assert_eq!(tags.title, None);
+    }
+
+    #[test]
+    fn id3_titles_are_read() {
+        let mut file = b"ID3\x03\x00\x00\x00\x00\x00\x10".to_vec();
+        file.extend(b"TIT2\0\0\0\x06\0\0\x00Intro");
+        file.extend(mp3(16000, None));
+        let tags = read_mp3_bytes(file).unwrap();
+        assert_eq!(tags.title, Some("Intro".to_string()));
+    }
+
+    #[test]
+    fn constant_bitrate_durations_come_from_the_length() {
+        // 16000 bytes at 128kbps
+        assert_eq!(read_mp3_bytes(mp3(16000, None)).unwrap().duration, 1000);
+    }
+
+    #[test]
+    fn xing_frames_give_the_duration() {
+        // 100 frames of 1152 samples at 44100Hz, whatever the length
+        assert_eq!(read_mp3_bytes(mp3(16000, Some(100))).unwrap().duration, 2612);
+    }
+}
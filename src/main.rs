@@ -5,22 +5,65 @@ extern crate websocket;
 extern crate hyper;
 extern crate serde;
 extern crate serde_json;
-
-use std::mem;
+extern crate time;
+extern crate toml;
+extern crate getopts;
+#[macro_use]
+extern crate log;
+extern crate env_logger;
+extern crate crypto;
+extern crate rustc_serialize;
+extern crate unicode_normalization;
+extern crate rand;
+
+use std::collections::HashMap;
+use std::env;
+use std::io::{self, Write};
+use std::net::Shutdown;
+use std::path::Path;
+use std::process;
 use std::thread;
-use std::sync::mpsc;
-use std::collections::{HashMap, BTreeMap, VecDeque};
-use websocket::{Server, Message, Sender, Receiver};
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::AtomicUsize;
+use hyper::header::{Authorization, Bearer};
+use hyper::uri::RequestUri;
+use websocket::{Server, Message, Receiver};
 use websocket::header::WebSocketProtocol;
 use websocket::stream::WebSocketStream;
-use websocket::server::Connection;
-use websocket::result::WebSocketResult;
+use websocket::server::{Connection, Request};
+use websocket::result::{WebSocketResult, WebSocketError};
+
+macro_rules! ret_err(
+    ($e:expr) => {{
+        match $e {
+            Ok(v) => v,
+            Err(e) => { error!("Line {}: {}", line!(), e); return; }
+        }
+    }}
+);
 
 mod api;
-use api::IngressMessage;
+use api::{IngressFrame, IngressMessage};
+mod auth;
+use auth::{Authenticator, AuthError, Identity};
+mod channel;
+use channel::{ChanMessage, NewClient, Rejection};
+mod clock;
+mod config;
+use config::{AuthConfig, Config, ConfigError};
+mod form;
+mod library;
+mod media;
+mod moderation;
+mod nicks;
+mod outbox;
+mod playlists;
 mod policy;
-
-use policy::Policy;
+mod ratelimit;
+mod rooms;
+mod session;
+mod timer;
+mod web;
 
 mod client {
     use websocket::client::Client;
@@ -33,24 +76,10 @@ mod client {
     pub type WsReceiver = R<WSS>;
     pub type WsClient = Client<DF, S<WSS>, R<WSS>>;
 }
-use self::client::{WsSender, WsReceiver};
-
-macro_rules! ret_err(
-    ($e:expr) => {{
-        match $e {
-            Ok(v) => v,
-            Err(e) => { println!("Line {}: {}", line!(), e); return; }
-        }
-    }}
-);
+use self::client::WsReceiver;
 
 type WsConn = Connection<WebSocketStream, WebSocketStream>;
-
-enum ChanMessage {
-    Introduce(UserId, WsSender),
-    Status(String),
-    Message(UserId, api::IngressMessage),
-}
+type WsRequest = Request<WebSocketStream, WebSocketStream>;
 
 pub enum User {
     Anonymous,
@@ -65,6 +94,13 @@ impl User {
         }
     }
 
+    fn is_authenticated(&self) -> bool {
+        match *self {
+            User::Registered(ref ru) => ru.oauth_token.is_some(),
+            _ => false
+        }
+    }
+
     fn nick(&self) -> &str {
         match *self {
             User::Anonymous => "Anonymous",
@@ -72,10 +108,22 @@ impl User {
         }
     }
 
-    fn policy(&self) -> &Policy {
+    fn role(&self) -> api::Role {
         match *self {
-            User::Anonymous => policy::ANONYMOUS,
-            User::Registered(ref ru) => &*ru.policy,
+            User::Anonymous => api::Role::Guest,
+            User::Registered(ref ru) => ru.role,
+        }
+    }
+
+    fn set_role(&mut self, role: api::Role) {
+        if let User::Registered(ref mut ru) = *self {
+            ru.role = role;
+        }
+    }
+
+    fn rename(&mut self, nick: &str) {
+        if let User::Registered(ref mut ru) = *self {
+            ru.nick = nick.to_string();
         }
     }
 }
@@ -83,7 +131,7 @@ impl User {
 pub struct RegisteredUser {
     oauth_token: Option<String>,
     nick: String,
-    policy: Box<Policy>,
+    role: api::Role,
 }
 
 impl User {
@@ -91,11 +139,19 @@ impl User {
         User::Anonymous
     }
 
-    pub fn registered(nick: &str) -> User {
+    pub fn registered(nick: &str, role: api::Role) -> User {
         User::Registered(RegisteredUser {
             oauth_token: None,
             nick: nick.to_string(),
-            policy: Box::new(policy::DefaultPolicy),
+            role: role,
+        })
+    }
+
+    pub fn authenticated(identity: Identity, role: api::Role) -> User {
+        User::Registered(RegisteredUser {
+            oauth_token: Some(identity.token),
+            nick: identity.nick,
+            role: role,
         })
     }
 }
@@ -104,215 +160,413 @@ impl User {
 #[derive(Serialize, Deserialize)]
 pub struct UserId(pub u64);
 
-struct Channel {
-    // nicks: HashMap<String, UserId>,
-    users: HashMap<UserId, User>,
-    clients: BTreeMap<UserId, WsSender>,
-    dj_queue: VecDeque<UserId>,
-    play_queue: Vec<api::PlayItem>,
-    rx: mpsc::Receiver<ChanMessage>,
+/// Makes a best effort at finding the request id and the kind of request a
+/// malformed frame was meant to be, for the benefit of the resulting error.
+fn frame_info(frame: &str) -> (Option<u64>, Option<String>) {
+    let value: serde_json::Value = match serde_json::from_str(frame) {
+        Ok(value) => value,
+        Err(_) => return (None, None),
+    };
+    let id = value.find("id").and_then(|id| id.as_u64());
+    let request = value.find("request").unwrap_or(&value);
+    let kind = request.as_array()
+        .and_then(|items| items.get(0))
+        .and_then(|kind| kind.as_string())
+        .map(|kind| kind.to_string());
+    (id, kind)
 }
 
-impl Channel {
-    pub fn new(rx: mpsc::Receiver<ChanMessage>) -> Channel {
-        Channel {
-            // nicks: HashMap::new(),
-            users: HashMap::new(),
-            clients: BTreeMap::new(),
-            dj_queue: VecDeque::new(),
-            play_queue: Vec::new(),
-            rx: rx,
-        }
-    }
-
-    pub fn dispatch_msg(&mut self, msg: api::EgressMessage) {
-        let message = serde_json::to_string(&msg).unwrap();
-        let mut dead_uid = Vec::new();
-
-        for (uid, client) in self.clients.iter_mut() {
-            if let Err(err) = client.send_message(Message::Text(message.clone())) {
-                println!("dropping a dead client: {:?}", err);
-                dead_uid.push(*uid);
-            }
-        }
-
-        for uid in dead_uid.into_iter() {
-            self.clients.remove(&uid);
-        }
-    }
-
-    fn handle_register(&mut self, uid: UserId, reg: &api::RegisterMessage) {
-        {
-            let user = self.users.get_mut(&uid).unwrap();
-            if !user.is_anonymous() {
-                // FIXME: nick-changing not support ATM
-                return;
-            }
-            mem::replace(user, User::registered(&reg.nick));
-        }
-        self.dispatch_msg(api::EgressMessage::Join(api::Join {
-            when: 0,
-            uid: uid,
-            nick: reg.nick.clone(),
-        }));
-    }
-
-    fn handle_dj_queue(&mut self, uid: UserId) {
-        if !self.dj_queue.iter().any(|&u| u == uid) {
-            self.dj_queue.push_back(uid)
-        }
-    }
-
-    fn handle_dj_unqueue(&mut self, uid: UserId) {
-        let old = mem::replace(&mut self.dj_queue, VecDeque::new());
-        self.dj_queue.extend(old.into_iter().filter(|&u| u != uid));
-    }
-
-    fn handle_message(&mut self, uid: UserId, msg: &str) {
-        self.dispatch_msg(api::EgressMessage::UserMessage(api::UserMessage {
-            when: 0,
-            uid: uid,
-            body: msg.to_string(),
-        }));
-    }
-
-    pub fn handle_msg(&mut self, uid: UserId, msg: &api::IngressMessage) {
-        use api::IngressMessage as IM;
-        if let Some(user) = self.users.get(&uid) {
-            if !user.policy().allow(msg) {
-                println!("user {:?} send disallowed message: {:?}", user.nick(), msg);
-                return;
-            }
-        } else {
-            println!("{:?} not found. dropping message", uid);
-            return;
-        }
-
-        match *msg {
-            IM::Register(ref reg) => self.handle_register(uid, reg),
-            IM::Disconnect => {
-                self.dispatch_msg(api::EgressMessage::Part(api::Part {
-                    when: 0,
-                    uid: uid,
-                }));
-            },
-            IM::Skip => {
-                let pbm = api::PlaybackMessage::Skip(api::Skip { uid: uid });
-                self.dispatch_msg(api::EgressMessage::PlaybackMessage(pbm));
-            },
-            IM::Part => (),
-            IM::DjQueue => self.handle_dj_queue(uid),
-            IM::DjUnqueue => self.handle_dj_unqueue(uid),
-            IM::Message(ref msg) => self.handle_message(uid, msg),
-        }
-    }
-
-    pub fn run(mut self) {
-        loop {
-            let message = ret_err!(self.rx.recv());
-            match message {
-                ChanMessage::Introduce(uid, new_client) => {
-                    self.clients.insert(uid, new_client);
-                },
-                ChanMessage::Status(body) => {
-                    self.dispatch_msg(api::EgressMessage::StatusMessage(api::StatusMessage {
-                        when: 0,
-                        body: body,
-                    }))
-                },
-                ChanMessage::Message(uid, msg) => {
-                    self.handle_msg(uid, &msg)
-                }
-            }
-        }
+fn auth_rejection(err: AuthError) -> Rejection {
+    match err {
+        AuthError::Rejected(msg) => Rejection::new(api::ErrorCode::Unauthorized, &msg),
+        AuthError::Unavailable(msg) => {
+            warn!("unable to check token: {}", msg);
+            Rejection::new(api::ErrorCode::Unavailable, "unable to check token")
+        },
     }
 }
 
-
-fn client_thread(sender: mpsc::Sender<ChanMessage>, user: UserId, mut client_rx: WsReceiver) {
+fn client_thread(sender: rooms::Room,
+                 user: UserId,
+                 conn: u64,
+                 max_frame_len: usize,
+                 authenticator: Option<Arc<Authenticator>>,
+                 mut client_rx: WsReceiver) {
     let client_addr = ret_err!(client_rx.get_mut().peer_addr());
+    // set once the room has closed behind our back
+    let mut room_gone = false;
 
     for msg in client_rx.incoming_messages() {
         match msg {
             Ok(Message::Text(msg)) => {
-                let data: IngressMessage = match serde_json::from_str(&msg) {
-                    Ok(imsg) => imsg,
-                    Err(err) => {
-                        println!("client {} sent invalid message (disconnecting): {:?}", client_addr, err);
+                if max_frame_len < msg.len() {
+                    info!("client {} sent an overlong frame", client_addr);
+                    let rejection = Rejection::new(api::ErrorCode::TooLarge, "frame is too long");
+                    if sender.send(ChanMessage::Invalid(user, None, rejection, None)).is_err() {
+                        room_gone = true;
                         break;
                     }
+                    continue;
+                }
+                let data: IngressFrame = match serde_json::from_str(&msg) {
+                    Ok(frame) => frame,
+                    Err(err) => {
+                        info!("client {} sent invalid message: {:?}", client_addr, err);
+                        let rejection = Rejection::new(api::ErrorCode::Malformed, &err.to_string());
+                        let (id, request) = frame_info(&msg);
+                        if sender.send(ChanMessage::Invalid(user, id, rejection, request)).is_err() {
+                            room_gone = true;
+                            break;
+                        }
+                        continue;
+                    }
                 };
-                let disconnect = if let IngressMessage::Disconnect = data { true } else { false };
-                sender.send(ChanMessage::Message(user, data)).unwrap();
-                if disconnect {
+                // the room needs to hear about this one, since it means the
+                // user is leaving rather than dropping out for a moment
+                if let IngressMessage::Disconnect = data.request {
+                    room_gone = sender.send(ChanMessage::Message(user, data)).is_err();
+                    break;
+                }
+                // checking a token may take a while, so it's done here
+                // rather than holding up the room
+                if let IngressMessage::Authenticate(ref auth) = data.request {
+                    if let Some(ref authenticator) = authenticator {
+                        let result = authenticator.authenticate(&auth.token).map_err(auth_rejection);
+                        if sender.send(ChanMessage::Authenticated(user, data.id, result)).is_err() {
+                            room_gone = true;
+                            break;
+                        }
+                        continue;
+                    }
+                }
+                if sender.send(ChanMessage::Message(user, data)).is_err() {
+                    room_gone = true;
                     break;
                 }
             },
-            Ok(Message::Close(_)) => {
-                sender.send(ChanMessage::Message(user, IngressMessage::Disconnect)).unwrap();
-            },
+            Ok(Message::Close(_)) => break,
             Ok(unhandled) => {
-                println!("Unhandled: {:?}", unhandled);
+                debug!("Unhandled: {:?}", unhandled);
             },
             Err(err) => {
-                println!("err: {:?}", err);
+                info!("err: {:?}", err);
                 break;
             }
         }
     }
+
+    if room_gone {
+        info!("room went away under client {}", client_addr);
+        let _ = client_rx.get_mut().shutdown(Shutdown::Both);
+        return;
+    }
+    // however we got here, the client is gone now
+    let _ = sender.send(ChanMessage::Disconnected(user, conn));
+}
+
+/// Turns a handshake down with a 400 response.
+fn refuse<T>(request: WsRequest, reason: String) -> WebSocketResult<T> {
+    try!(request.fail().send_into_inner());
+    Err(WebSocketError::RequestError(reason))
+}
+
+/// The token a client presented in its handshake, either as the `token`
+/// query parameter (browsers can't set headers on websockets) or as a
+/// bearer token in the Authorization header.
+fn handshake_token(request: &WsRequest) -> Option<String> {
+    if let RequestUri::AbsolutePath(ref path) = request.url {
+        if let Some(token) = form::query_param(path, "token") {
+            return Some(token);
+        }
+    }
+    request.headers.get::<Authorization<Bearer>>().map(|auth| auth.0.token.clone())
 }
 
-fn introduce(sender: mpsc::Sender<ChanMessage>, uid: UserId, conn: WsConn) -> WebSocketResult<WsSender> {
-    let request = try!(conn.read_request());
+/// The resume token a reconnecting client presented as the `resume` query
+/// parameter, if any.
+fn handshake_resume(request: &WsRequest) -> Option<String> {
+    match request.url {
+        RequestUri::AbsolutePath(ref path) => form::query_param(path, "resume"),
+        _ => None,
+    }
+}
+
+/// Carries out a websocket handshake and hands the client to its room.
+/// Handshakes may take a while, particularly when a token has to be
+/// checked, so this runs on a thread of the connection's own. A room which
+/// isn't running yet is only started once the handshake has been accepted.
+fn introduce(config: &Config,
+             authenticator: &Option<Arc<Authenticator>>,
+             rooms: &Mutex<rooms::Rooms>,
+             conn_id: u64,
+             conn: WsConn)
+    -> WebSocketResult<(rooms::Room, NewClient)>
+{
+    let mut request = try!(conn.read_request());
+    let addr = try!(request.get_mut_reader().peer_addr());
     let headers = request.headers.clone();
+    let resume = handshake_resume(&request);
+
+    let slug = match rooms::slug_from_uri(&request.url) {
+        Some(slug) => slug,
+        None => return refuse(request, "invalid room name".to_string()),
+    };
+    let checked = rooms.lock().unwrap().check(&slug);
+    if let Err(reason) = checked {
+        return refuse(request, reason);
+    }
+
+    let identity = match (handshake_token(&request), authenticator.as_ref()) {
+        (Some(token), Some(authenticator)) => match authenticator.authenticate(&token) {
+            Ok(identity) => Some(identity),
+            Err(err) => return refuse(request, err.to_string()),
+        },
+        (Some(_), None) => return refuse(request, "authentication is not enabled".to_string()),
+        (None, _) => None,
+    };
+
+    // a room which isn't running has nobody banned from it, and one which
+    // is stays running while this is held
+    let running = rooms.lock().unwrap().get(&slug);
+    if let Some(ref room) = running {
+        let (reply_tx, reply_rx) = mpsc::channel();
+        let nick = identity.as_ref().map(|identity| identity.nick.clone());
+        // if the room has gone, the reply sender goes with the message
+        let _ = room.send(ChanMessage::Admit(addr.ip(), nick, reply_tx));
+        match reply_rx.recv() {
+            Ok(Ok(())) => (),
+            Ok(Err(reason)) => {
+                info!("refused {} entry to room {:?}: {}", addr, slug, reason);
+                return refuse(request, reason);
+            },
+            Err(_) => return refuse(request, format!("room {:?} has closed", slug)),
+        }
+    }
 
     try!(request.validate());
     let mut response = request.accept();
     // assert_eq!(response.status, StatusCode::Ok);
 
     if let Some(&WebSocketProtocol(ref protocols)) = headers.get() {
-        if protocols.contains(&("rust-websocket".to_string())) {
+        if protocols.contains(&config.protocol) {
             // We have a protocol we want to use
-            response.headers.set(WebSocketProtocol(vec!["rust-websocket".to_string()]));
+            response.headers.set(WebSocketProtocol(vec![config.protocol.clone()]));
         }
     }
 
-    let mut client = try!(response.send());
-    let ip = client.get_mut_sender().get_mut().peer_addr().unwrap();
-    println!("Connection from {}", ip);
+    let client = try!(response.send());
+    let room = match running {
+        Some(room) => room,
+        None => {
+            let started = rooms.lock().unwrap().get_or_start(&slug);
+            try!(started.map_err(WebSocketError::RequestError))
+        },
+    };
+    info!("Connection from {} to room {:?}", addr, slug);
+
+    // the room answers straight away, and only once the handshake is done,
+    // so a failed handshake can't hang up the connection it was resuming
+    let uid = match resume {
+        Some(token) => {
+            let (reply_tx, reply_rx) = mpsc::channel();
+            // if the room has gone, the reply sender goes with the message
+            let _ = room.send(ChanMessage::Resume(token, conn_id, reply_tx));
+            match reply_rx.recv() {
+                Ok(resumed) => resumed.unwrap_or(UserId(conn_id)),
+                Err(_) => return Err(WebSocketError::RequestError(format!("room {:?} has closed", slug))),
+            }
+        },
+        None => UserId(conn_id),
+    };
 
     let (client_tx, client_rx) = client.split();
-    thread::spawn(move || client_thread(sender, uid, client_rx));
-    Ok(client_tx)
+    let sender = room.clone();
+    let max_frame_len = config.max_frame_len;
+    let authenticator = authenticator.clone();
+    thread::spawn(move || {
+        client_thread(sender, uid, conn_id, max_frame_len, authenticator, client_rx)
+    });
+    let outbox = outbox::Outbox::start(client_tx, config.outbound_queue_len, config.overflow_policy);
+    Ok((room, NewClient {
+        uid: uid,
+        conn: conn_id,
+        ip: addr.ip(),
+        outbox: outbox,
+        identity: identity,
+    }))
 }
 
 
-fn start_channel() -> mpsc::Sender<ChanMessage> {
-    let (rx, tx) = mpsc::channel();
-    thread::spawn(move || Channel::new(tx).run());
-    rx
+fn load_config(matches: &getopts::Matches) -> Result<Config, ConfigError> {
+    let mut config = match matches.opt_str("config") {
+        Some(path) => try!(Config::load(&path)),
+        None => Config::default(),
+    };
+    if let Some(bind) = matches.opt_str("bind") {
+        config.bind = bind;
+    }
+    if let Some(protocol) = matches.opt_str("protocol") {
+        config.protocol = protocol;
+    }
+    if let Some(level) = matches.opt_str("log-level") {
+        try!(config.set_log_level(&level));
+    }
+    for slug in matches.opt_strs("room").iter() {
+        if config.rooms.iter().all(|r| r.slug != *slug) {
+            let room = config.room_defaults.for_slug(slug);
+            try!(config.add_room(room));
+        }
+    }
+    if matches.opt_present("no-dynamic-rooms") {
+        config.dynamic_rooms = false;
+    }
+    Ok(config)
+}
+
+fn init_logging(level: log::LogLevelFilter) {
+    let mut builder = env_logger::LogBuilder::new();
+    builder.filter(None, level);
+    // RUST_LOG still works for finer-grained control
+    if let Ok(spec) = env::var("RUST_LOG") {
+        builder.parse(&spec);
+    }
+    builder.init().unwrap();
 }
 
 fn main() {
-    // Start listening for WebSocket connections
-    let ws_server = Server::bind("0.0.0.0:2794").unwrap();
+    let args: Vec<String> = env::args().collect();
+
+    let mut opts = getopts::Options::new();
+    opts.optopt("c", "config", "read settings from a TOML file", "FILE");
+    opts.optopt("b", "bind", "address to listen on (default 0.0.0.0:2794)", "ADDR");
+    opts.optopt("", "protocol", "websocket subprotocol to agree to", "NAME");
+    opts.optopt("l", "log-level", "off, error, warn, info, debug or trace", "LEVEL");
+    opts.optmulti("r", "room", "start a room at startup (may be repeated)", "SLUG");
+    opts.optflag("", "no-dynamic-rooms", "refuse connections to rooms which aren't listed");
+    opts.optopt("", "issue-token", "print a token for NICK, signed with the hmac secret, and exit", "NICK");
+    opts.optopt("", "token-ttl", "how long issued tokens last (default 86400)", "SECONDS");
+    opts.optflag("h", "help", "print this help");
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(matches) => matches,
+        Err(err) => {
+            let _ = writeln!(io::stderr(), "{}: {}", args[0], err);
+            process::exit(2);
+        }
+    };
+    if matches.opt_present("help") {
+        print!("{}", opts.usage(&format!("Usage: {} [options]", args[0])));
+        return;
+    }
+    let config = match load_config(&matches) {
+        Ok(config) => config,
+        Err(err) => {
+            let _ = writeln!(io::stderr(), "{}: {}", args[0], err);
+            process::exit(1);
+        }
+    };
+    init_logging(config.log_level);
+
+    if let Some(nick) = matches.opt_str("issue-token") {
+        let secret = match config.auth {
+            AuthConfig::Hmac { ref secret } => secret,
+            _ => {
+                let _ = writeln!(io::stderr(), "{}: issuing tokens needs hmac authentication", args[0]);
+                process::exit(1);
+            }
+        };
+        let ttl: u64 = match matches.opt_str("token-ttl").map(|ttl| ttl.parse()) {
+            None => 86400,
+            Some(Ok(ttl)) => ttl,
+            Some(Err(_)) => {
+                let _ = writeln!(io::stderr(), "{}: --token-ttl must be a number of seconds", args[0]);
+                process::exit(2);
+            }
+        };
+        let expires = time::get_time().sec as u64 + ttl;
+        println!("{}", auth::HmacVerifier::new(secret.as_bytes()).issue(&nick, expires));
+        return;
+    }
+    let authenticator = auth::from_config(&config.auth);
 
-    let chan_rx = start_channel();
+    // Start listening for WebSocket connections
+    let ws_server = match Server::bind(&config.bind[..]) {
+        Ok(server) => server,
+        Err(err) => {
+            error!("unable to listen on {}: {}", config.bind, err);
+            process::exit(1);
+        }
+    };
+    info!("listening on {}", config.bind);
+
+    let timebase = clock::Timebase::new();
+    let timer = timer::Timer::start();
+    // the config makes sure there is [http] to stream a library from
+    let library = config.library.as_ref().and_then(|settings| config.http.as_ref().map(|http| {
+        match library::Library::index(Path::new(&settings.dir), &http.url) {
+            Ok(library) => Arc::new(library),
+            Err(err) => {
+                error!("unable to index {}: {}", settings.dir, err);
+                process::exit(1);
+            }
+        }
+    }));
+    let providers = Arc::new(media::Providers::from_config(&config.providers,
+                                                           config.media_cache.as_ref(),
+                                                           library.clone()));
+    let playlist_dir = config.data_dir.as_ref().map(|dir| Path::new(dir).join("playlists"));
+    let playlists = Arc::new(playlists::Store::new(playlist_dir, config.max_playlists, config.max_playlist_len));
+    // serves for as long as main runs
+    let _web_server = config.http.as_ref().map(|http| {
+        let web = web::Web {
+            timebase: timebase,
+            library: library,
+            playlists: playlists.clone(),
+            providers: providers.clone(),
+            authenticator: authenticator.clone(),
+            max_duration: media::duration_limit(config.room_defaults.max_duration),
+            rate_limits: config.room_defaults.rate_limits.clone(),
+            limiters: Mutex::new(HashMap::new()),
+            imports: AtomicUsize::new(0),
+        };
+        match web::serve(&http.bind, web) {
+            Ok(listening) => {
+                info!("serving http on {}", http.bind);
+                listening
+            },
+            Err(err) => {
+                error!("unable to listen on {}: {}", http.bind, err);
+                process::exit(1);
+            }
+        }
+    });
+    let mut rooms = rooms::Rooms::new(timebase, timer, config.clone(), providers, playlists);
+    rooms.start_listed();
+    let rooms = Arc::new(Mutex::new(rooms));
+    let config = Arc::new(config);
 
-    let mut user_id = 1;
+    // every connection gets a serial, which doubles as the uid of the user
+    // unless it resumes an earlier session
+    let mut conn_id = 1;
 
     for connection in ws_server {
         if let Ok(conn) = connection {
-            let new_uid = UserId(user_id);
-            user_id += 1;
-            match introduce(chan_rx.clone(), new_uid, conn) {
-                Ok(client_rx) => {
-                    chan_rx.send(ChanMessage::Introduce(new_uid, client_rx)).unwrap();
-                },
-                Err(err) => {
-                    println!("lol error: {:?}", err);
+            let new_conn = conn_id;
+            conn_id += 1;
+            let config = config.clone();
+            let authenticator = authenticator.clone();
+            let rooms = rooms.clone();
+            thread::spawn(move || {
+                match introduce(&config, &authenticator, &rooms, new_conn, conn) {
+                    Ok((room, client)) => {
+                        // the room may have gone away during the handshake
+                        let _ = room.send(ChanMessage::Introduce(client));
+                    },
+                    Err(err) => {
+                        warn!("lol error: {:?}", err);
+                    }
                 }
-            }
+            });
         }
     }
-}
\ No newline at end of file
+}
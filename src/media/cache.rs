@@ -0,0 +1,147 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use rand::{self, Rng};
+use serde_json;
+use time;
+
+use api::MediaInfo;
+use super::{MediaError, Provider};
+
+/// What a provider said about a track, as kept on disk.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    // when the provider was asked, in milliseconds since the unix epoch
+    fetched: u64,
+    info: Option<MediaInfo>,
+    // set if the track wasn't found
+    not_found: Option<String>,
+    // set if the track can't be played
+    unplayable: Option<String>,
+}
+
+impl Entry {
+    fn new(fetched: u64, result: &Result<MediaInfo, MediaError>) -> Option<Entry> {
+        let mut entry = Entry {
+            fetched: fetched,
+            info: None,
+            not_found: None,
+            unplayable: None,
+        };
+        match *result {
+            Ok(ref info) => entry.info = Some(info.clone()),
+            Err(MediaError::NotFound(ref msg)) => entry.not_found = Some(msg.clone()),
+            Err(MediaError::Unplayable(ref msg)) => entry.unplayable = Some(msg.clone()),
+            // not the track's fault, so not worth remembering
+            Err(_) => return None,
+        }
+        Some(entry)
+    }
+
+    fn result(&self) -> Result<MediaInfo, MediaError> {
+        if let Some(ref info) = self.info {
+            return Ok(info.clone());
+        }
+        if let Some(ref msg) = self.unplayable {
+            return Err(MediaError::Unplayable(msg.clone()));
+        }
+        Err(MediaError::NotFound(self.not_found.clone().unwrap_or(String::new())))
+    }
+}
+
+fn now_ms() -> u64 {
+    let now = time::get_time();
+    now.sec as u64 * 1000 + now.nsec as u64 / 1_000_000
+}
+
+/// Remembers what another provider says about tracks in a directory of
+/// JSON files, one per track. Tracks which were found are remembered for
+/// `ttl` milliseconds and tracks which weren't for `miss_ttl`. When the
+/// provider can't be reached, whatever was last heard is used, however old.
+pub struct CachedProvider {
+    inner: Arc<Provider>,
+    dir: PathBuf,
+    ttl: u64,
+    miss_ttl: u64,
+}
+
+impl CachedProvider {
+    pub fn new(inner: Arc<Provider>, dir: PathBuf, ttl: u64, miss_ttl: u64) -> CachedProvider {
+        CachedProvider {
+            inner: inner,
+            dir: dir,
+            ttl: ttl,
+            miss_ttl: miss_ttl,
+        }
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        let mut hasher = Sha256::new();
+        hasher.input_str(id);
+        self.dir.join(format!("{}.json", hasher.result_str()))
+    }
+
+    fn load(&self, id: &str) -> Option<Entry> {
+        let mut text = String::new();
+        match File::open(self.path(id)).and_then(|mut f| f.read_to_string(&mut text)) {
+            Ok(_) => (),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return None,
+            Err(err) => {
+                warn!("unable to read cached metadata for {:?}: {}", id, err);
+                return None;
+            }
+        }
+        serde_json::from_str(&text).ok()
+    }
+
+    fn store(&self, id: &str, entry: &Entry) -> io::Result<()> {
+        try!(fs::create_dir_all(&self.dir));
+        let path = self.path(id);
+        // written aside and renamed into place, so readers never see half
+        // an entry; the name is made up each time, so two writes of the
+        // same entry don't share a file
+        let suffix: u64 = rand::thread_rng().gen();
+        let partial = path.with_extension(format!("json.{:016x}.partial", suffix));
+        let text = serde_json::to_string(entry).unwrap();
+        let stored = File::create(&partial)
+            .and_then(|mut file| file.write_all(text.as_bytes()))
+            .and_then(|_| fs::rename(&partial, &path));
+        if stored.is_err() {
+            let _ = fs::remove_file(&partial);
+        }
+        stored
+    }
+}
+
+impl Provider for CachedProvider {
+    fn resolve(&self, id: &str) -> Result<MediaInfo, MediaError> {
+        let now = now_ms();
+        let cached = self.load(id);
+        if let Some(ref entry) = cached {
+            let ttl = if entry.info.is_some() { self.ttl } else { self.miss_ttl };
+            if now.saturating_sub(entry.fetched) < ttl {
+                return entry.result();
+            }
+        }
+
+        let result = self.inner.resolve(id);
+        match Entry::new(now, &result) {
+            Some(entry) => {
+                if let Err(err) = self.store(id, &entry) {
+                    warn!("unable to cache metadata for {:?}: {}", id, err);
+                }
+                result
+            },
+            None => match cached {
+                Some(entry) => {
+                    info!("using stale metadata for {:?}", id);
+                    entry.result()
+                },
+                None => result,
+            },
+        }
+    }
+}
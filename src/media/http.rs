@@ -0,0 +1,83 @@
+use std::fmt;
+use std::io::Read;
+use std::time::Duration;
+use hyper;
+use serde_json;
+
+use api::{MediaInfo, ProviderKind};
+use form;
+use super::{MediaError, Provider};
+
+// how long to wait on the metadata service before giving up on it, in
+// milliseconds
+const TIMEOUT: u64 = 10_000;
+
+#[derive(Deserialize)]
+struct MetadataResponse {
+    title: String,
+    artist: Option<String>,
+    // in milliseconds
+    duration: u64,
+    thumbnail: Option<String>,
+    // absent means playable
+    playable: Option<bool>,
+    // why it isn't playable, e.g. "region locked"
+    reason: Option<String>,
+}
+
+/// Asks an HTTP service about tracks, with a GET to
+/// `<endpoint>?provider=<kind>&id=<id>`. The service answers 404 for
+/// tracks which don't exist, and otherwise with a `MetadataResponse`.
+pub struct HttpProvider {
+    endpoint: String,
+    kind: ProviderKind,
+}
+
+impl HttpProvider {
+    pub fn new(endpoint: &str, kind: ProviderKind) -> HttpProvider {
+        HttpProvider {
+            endpoint: endpoint.to_string(),
+            kind: kind,
+        }
+    }
+
+    fn url(&self, id: &str) -> String {
+        let separator = if self.endpoint.contains('?') { '&' } else { '?' };
+        format!("{}{}provider={}&id={}", self.endpoint, separator, self.kind.name(), form::encode(id))
+    }
+}
+
+impl Provider for HttpProvider {
+    fn resolve(&self, id: &str) -> Result<MediaInfo, MediaError> {
+        let unavailable = |err: &fmt::Display| MediaError::Unavailable(err.to_string());
+
+        let mut client = hyper::Client::new();
+        client.set_read_timeout(Some(Duration::from_millis(TIMEOUT)));
+        client.set_write_timeout(Some(Duration::from_millis(TIMEOUT)));
+        let mut response = try!(client.get(&self.url(id)[..]).send().map_err(|e| unavailable(&e)));
+        if response.status == hyper::NotFound {
+            return Err(MediaError::NotFound(format!("no such track: {}", id)));
+        }
+        if response.status != hyper::Ok {
+            return Err(MediaError::Unavailable(format!("metadata endpoint said {}", response.status)));
+        }
+        let mut text = String::new();
+        try!(response.read_to_string(&mut text).map_err(|e| unavailable(&e)));
+        let meta: MetadataResponse = try!(serde_json::from_str(&text).map_err(|e| unavailable(&e)));
+
+        if meta.playable == Some(false) {
+            let reason = meta.reason.unwrap_or("not playable".to_string());
+            return Err(MediaError::Unplayable(reason));
+        }
+        if meta.duration == 0 {
+            return Err(MediaError::Unplayable("track has no duration".to_string()));
+        }
+        Ok(MediaInfo {
+            title: meta.title,
+            artist: meta.artist,
+            duration: meta.duration,
+            thumbnail: meta.thumbnail,
+            stream_url: None,
+        })
+    }
+}
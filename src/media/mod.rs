@@ -0,0 +1,287 @@
+use std::ascii::AsciiExt;
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher, SipHasher};
+use std::path::Path;
+use std::sync::Arc;
+
+use api::{MediaInfo, MediaRef, ProviderKind};
+use config::{MediaCacheConfig, ProviderConfig};
+use library::Library;
+
+pub use self::cache::CachedProvider;
+pub use self::http::HttpProvider;
+
+mod cache;
+mod http;
+
+#[derive(Debug)]
+pub enum MediaError {
+    // the reference or the client's description of it is no good
+    Invalid(String),
+    // the provider has never heard of it
+    NotFound(String),
+    // it exists, but can't be played here
+    Unplayable(String),
+    // the provider couldn't be asked
+    Unavailable(String),
+}
+
+impl fmt::Display for MediaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MediaError::Invalid(ref msg) => write!(f, "invalid: {}", msg),
+            MediaError::NotFound(ref msg) => write!(f, "not found: {}", msg),
+            MediaError::Unplayable(ref msg) => write!(f, "unplayable: {}", msg),
+            MediaError::Unavailable(ref msg) => write!(f, "unavailable: {}", msg),
+        }
+    }
+}
+
+// no track may be longer than this, in milliseconds, whatever a room's
+// max_duration says; clients describing their own tracks can claim anything
+const MAX_DURATION: u64 = 24 * 60 * 60 * 1000;
+
+/// The longest track a room whose `max_duration` is `seconds` accepts, in
+/// milliseconds.
+pub fn duration_limit(seconds: usize) -> u64 {
+    match seconds as u64 {
+        0 => MAX_DURATION,
+        seconds => cmp::min(MAX_DURATION, seconds.saturating_mul(1000)),
+    }
+}
+
+/// Looks up what's known about tracks from one source.
+pub trait Provider: Send + Sync {
+    fn resolve(&self, id: &str) -> Result<MediaInfo, MediaError>;
+}
+
+/// How the tracks of each kind of provider are looked up.
+enum Source {
+    // the client's description of the track is taken on trust
+    Client,
+    Provider(Arc<Provider>),
+}
+
+/// The providers a server is configured with.
+pub struct Providers {
+    sources: HashMap<ProviderKind, Source>,
+}
+
+impl Providers {
+    pub fn from_config(config: &HashMap<ProviderKind, ProviderConfig>,
+                       cache: Option<&MediaCacheConfig>,
+                       library: Option<Arc<Library>>) -> Providers {
+        let mut sources = HashMap::new();
+        for (&kind, provider) in config.iter() {
+            let provider: Arc<Provider> = match *provider {
+                ProviderConfig::Client => {
+                    sources.insert(kind, Source::Client);
+                    continue;
+                },
+                ProviderConfig::Fake => Arc::new(FakeProvider),
+                ProviderConfig::Http { ref endpoint } => Arc::new(HttpProvider::new(endpoint, kind)),
+                ProviderConfig::Library => {
+                    // already on disk, so not worth caching
+                    if let Some(ref library) = library {
+                        sources.insert(kind, Source::Provider(library.clone()));
+                    }
+                    continue;
+                },
+            };
+            let provider: Arc<Provider> = match cache {
+                Some(cache) => {
+                    let dir = Path::new(&cache.dir).join(kind.name());
+                    let (ttl, miss_ttl) = (cache.ttl as u64 * 1000, cache.miss_ttl as u64 * 1000);
+                    Arc::new(CachedProvider::new(provider, dir, ttl, miss_ttl))
+                },
+                None => provider,
+            };
+            sources.insert(kind, Source::Provider(provider));
+        }
+        Providers { sources: sources }
+    }
+
+    /// Works out what `media` is, going by `claimed` only if the provider
+    /// is one whose clients are trusted. Clients never get to say where a
+    /// track streams from.
+    pub fn resolve(&self, media: &MediaRef, claimed: Option<&MediaInfo>) -> Result<MediaInfo, MediaError> {
+        try!(validate(media));
+        match self.sources.get(&media.provider) {
+            Some(&Source::Provider(ref provider)) => provider.resolve(&media.id),
+            Some(&Source::Client) => match claimed {
+                Some(info) if 0 < info.duration => {
+                    let mut info = info.clone();
+                    info.stream_url = None;
+                    Ok(info)
+                },
+                _ => Err(MediaError::Invalid("describe the track, with its duration".to_string())),
+            },
+            None => {
+                let msg = format!("{} tracks can't be played here", media.provider.name());
+                Err(MediaError::Unavailable(msg))
+            },
+        }
+    }
+}
+
+/// Checks that the id in `media` has the right shape for its provider.
+pub fn validate(media: &MediaRef) -> Result<(), MediaError> {
+    let id = &media.id[..];
+    let valid = match media.provider {
+        ProviderKind::Youtube => {
+            id.len() == 11 &&
+                id.chars().all(|c| c.is_alphanumeric() && c.is_ascii() || c == '-' || c == '_')
+        },
+        ProviderKind::Soundcloud | ProviderKind::Url => {
+            id.starts_with("https://") || id.starts_with("http://")
+        },
+        ProviderKind::Library => !id.is_empty(),
+    };
+    if valid {
+        Ok(())
+    } else {
+        let msg = format!("not a valid {} id", media.provider.name());
+        Err(MediaError::Invalid(msg))
+    }
+}
+
+/// Makes up tracks for any id, for trying clients out without the network.
+/// Ids starting with "missing" are not found and ids starting with
+/// "blocked" are unplayable; anything else gets a duration between one and
+/// five minutes, always the same for the same id.
+pub struct FakeProvider;
+
+impl Provider for FakeProvider {
+    fn resolve(&self, id: &str) -> Result<MediaInfo, MediaError> {
+        if id.starts_with("missing") {
+            return Err(MediaError::NotFound(id.to_string()));
+        }
+        if id.starts_with("blocked") {
+            return Err(MediaError::Unplayable(format!("{} is blocked", id)));
+        }
+        let mut hasher = SipHasher::new();
+        id.hash(&mut hasher);
+        let seconds = 60 + hasher.finish() % 240;
+        Ok(MediaInfo {
+            title: format!("Fake track {}", id),
+            artist: Some("Fake artist".to_string()),
+            duration: seconds * 1000,
+            thumbnail: None,
+            stream_url: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use api::{MediaInfo, MediaRef, ProviderKind};
+    use config::ProviderConfig;
+    use super::*;
+
+    fn providers() -> Providers {
+        let mut config = HashMap::new();
+        config.insert(ProviderKind::Youtube, ProviderConfig::Fake);
+        config.insert(ProviderKind::Url, ProviderConfig::Client);
+        Providers::from_config(&config, None, None)
+    }
+
+    fn media(provider: ProviderKind, id: &str) -> MediaRef {
+        MediaRef {
+            provider: provider,
+            id: id.to_string(),
+        }
+    }
+
+    fn info(duration: u64) -> MediaInfo {
+        MediaInfo {
+            title: "Intro".to_string(),
+            artist: None,
+            duration: duration,
+            thumbnail: None,
+            stream_url: None,
+        }
+    }
+
+    #[test]
+    fn fake_tracks_are_made_up_the_same_way_every_time() {
+        let providers = providers();
+        let track = media(ProviderKind::Youtube, "dQw4w9WgXcQ");
+        let first = providers.resolve(&track, None).unwrap();
+        let second = providers.resolve(&track, None).unwrap();
+        assert_eq!(first.duration, second.duration);
+        assert!(60 * 1000 <= first.duration && first.duration < 300 * 1000);
+    }
+
+    #[test]
+    fn fake_tracks_can_be_missing_or_blocked() {
+        let providers = providers();
+        match providers.resolve(&media(ProviderKind::Youtube, "missing0000"), None) {
+            Err(MediaError::NotFound(_)) => (),
+            other => panic!("expected NotFound, got {:?}", other),
+        }
+        match providers.resolve(&media(ProviderKind::Youtube, "blocked0000"), None) {
+            Err(MediaError::Unplayable(_)) => (),
+            other => panic!("expected Unplayable, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn fake_providers_ignore_claimed_info() {
+        let providers = providers();
+        let resolved = providers.resolve(&media(ProviderKind::Youtube, "dQw4w9WgXcQ"), Some(&info(1))).unwrap();
+        assert!(resolved.duration != 1);
+    }
+
+    #[test]
+    fn client_providers_take_claimed_info() {
+        let providers = providers();
+        let track = media(ProviderKind::Url, "http://example.com/intro.mp3");
+        assert_eq!(providers.resolve(&track, Some(&info(212000))).unwrap().duration, 212000);
+        match providers.resolve(&track, None) {
+            Err(MediaError::Invalid(_)) => (),
+            other => panic!("expected Invalid, got {:?}", other),
+        }
+        match providers.resolve(&track, Some(&info(0))) {
+            Err(MediaError::Invalid(_)) => (),
+            other => panic!("expected Invalid, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn client_providers_drop_claimed_stream_urls() {
+        let providers = providers();
+        let track = media(ProviderKind::Url, "http://example.com/intro.mp3");
+        let mut claimed = info(212000);
+        claimed.stream_url = Some("http://example.net/something-else.mp3".to_string());
+        assert_eq!(providers.resolve(&track, Some(&claimed)).unwrap().stream_url, None);
+    }
+
+    #[test]
+    fn unconfigured_providers_are_unavailable() {
+        let providers = providers();
+        let track = media(ProviderKind::Soundcloud, "https://soundcloud.com/artist/track");
+        match providers.resolve(&track, Some(&info(212000))) {
+            Err(MediaError::Unavailable(_)) => (),
+            other => panic!("expected Unavailable, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn ids_are_checked_before_providers_are_asked() {
+        assert!(validate(&media(ProviderKind::Youtube, "dQw4w9WgXcQ")).is_ok());
+        assert!(validate(&media(ProviderKind::Youtube, "dQw4w9WgXc")).is_err());
+        assert!(validate(&media(ProviderKind::Youtube, "dQw4w9WgX/Q")).is_err());
+        assert!(validate(&media(ProviderKind::Soundcloud, "soundcloud.com/artist/track")).is_err());
+        assert!(validate(&media(ProviderKind::Url, "https://example.com/a.mp3")).is_ok());
+        assert!(validate(&media(ProviderKind::Library, "")).is_err());
+        // an invalid id is refused even by providers which would find it
+        match providers().resolve(&media(ProviderKind::Youtube, "missing"), None) {
+            Err(MediaError::Invalid(_)) => (),
+            other => panic!("expected Invalid, got {:?}", other),
+        }
+    }
+}
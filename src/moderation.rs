@@ -0,0 +1,87 @@
+use std::net::IpAddr;
+
+use api::Role;
+use super::UserId;
+
+/// Who a ban keeps out of a room, or a mute keeps quiet. Either outlasts
+/// the connection it was placed on, so that reconnecting doesn't lift it.
+pub struct Sanction {
+    pub uid: UserId,
+    // the nick_key of the user, if they had authenticated
+    pub nick: Option<String>,
+    pub ip: Option<IpAddr>,
+    // None for a ban that never runs out
+    pub until: Option<u64>,
+    // the user's role when it was placed
+    pub role: Role,
+    // the role of the staff who placed it; None when the room did
+    pub by: Option<Role>,
+}
+
+impl Sanction {
+    fn expired(&self, now: u64) -> bool {
+        match self.until {
+            Some(until) => until <= now,
+            None => false,
+        }
+    }
+
+    fn matches(&self, uid: Option<UserId>, ip: Option<IpAddr>, nick: Option<&str>) -> bool {
+        let by_uid = uid == Some(self.uid);
+        let by_ip = match (self.ip, ip) {
+            (Some(banned), Some(ip)) => banned == ip,
+            _ => false,
+        };
+        let by_nick = match (self.nick.as_ref(), nick) {
+            (Some(banned), Some(nick)) => &banned[..] == nick,
+            _ => false,
+        };
+        by_uid || by_ip || by_nick
+    }
+}
+
+/// The bans, or the mutes, in force in a room. Expired ones are dropped as
+/// they're found.
+pub struct Sanctions {
+    sanctions: Vec<Sanction>,
+}
+
+impl Sanctions {
+    pub fn new() -> Sanctions {
+        Sanctions { sanctions: Vec::new() }
+    }
+
+    pub fn add(&mut self, sanction: Sanction) {
+        self.sanctions.push(sanction);
+    }
+
+    /// Lifts every sanction on the user `uid`, or on the address `ip` or
+    /// the nick_key `nick`, returning whether there were any.
+    pub fn remove(&mut self, uid: UserId, ip: Option<IpAddr>, nick: Option<&str>) -> bool {
+        let before = self.sanctions.len();
+        self.sanctions.retain(|sanction| !sanction.matches(Some(uid), ip, nick));
+        self.sanctions.len() != before
+    }
+
+    /// Lifts the sanction on the user `uid`, along with any others on the
+    /// address or nick_key it went by, returning whether there was one.
+    pub fn lift(&mut self, now: u64, uid: UserId) -> bool {
+        let keys = self.find(now, Some(uid), None, None).map(|sanction| (sanction.ip, sanction.nick.clone()));
+        match keys {
+            Some((ip, nick)) => self.remove(uid, ip, nick.as_ref().map(|nick| &nick[..])),
+            None => false,
+        }
+    }
+
+    /// The sanction on the user `uid`, a client connecting from `ip`, or
+    /// one authenticated as the user with the nick_key `nick`, if there is
+    /// one.
+    pub fn find(&mut self,
+                now: u64,
+                uid: Option<UserId>,
+                ip: Option<IpAddr>,
+                nick: Option<&str>) -> Option<&Sanction> {
+        self.sanctions.retain(|sanction| !sanction.expired(now));
+        self.sanctions.iter().find(|sanction| sanction.matches(uid, ip, nick))
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+use unicode_normalization::UnicodeNormalization;
+
+use super::UserId;
+
+#[derive(Debug)]
+pub enum NickError {
+    Invalid(&'static str),
+    Taken,
+}
+
+/// The form of a nick used to decide whether two nicks clash, so that
+/// "DJ", "dj" and "ｄｊ" all count as the same nick.
+pub fn nick_key(nick: &str) -> String {
+    let folded: String = nick.nfkc().collect::<String>().to_lowercase();
+    folded.nfkc().collect()
+}
+
+/// Checks that a nick is something we're willing to display.
+pub fn validate(nick: &str, max_len: usize) -> Result<(), NickError> {
+    if nick.trim().is_empty() {
+        return Err(NickError::Invalid("nick is empty"));
+    }
+    if nick.trim() != nick {
+        return Err(NickError::Invalid("nick has leading or trailing spaces"));
+    }
+    if max_len < nick.chars().count() {
+        return Err(NickError::Invalid("nick is too long"));
+    }
+    if nick.chars().any(|c| c.is_control()) {
+        return Err(NickError::Invalid("nick has control characters"));
+    }
+    Ok(())
+}
+
+/// Which user holds each nick in a room.
+pub struct NickIndex {
+    nicks: HashMap<String, UserId>,
+}
+
+impl NickIndex {
+    pub fn new() -> NickIndex {
+        NickIndex { nicks: HashMap::new() }
+    }
+
+    /// Claims `nick` for `uid`. Claiming a nick one already holds is fine.
+    pub fn reserve(&mut self, nick: &str, uid: UserId) -> Result<(), NickError> {
+        let key = nick_key(nick);
+        match self.nicks.get(&key) {
+            Some(&holder) if holder != uid => return Err(NickError::Taken),
+            _ => (),
+        }
+        self.nicks.insert(key, uid);
+        Ok(())
+    }
+
+    pub fn release(&mut self, nick: &str, uid: UserId) {
+        let key = nick_key(nick);
+        if self.nicks.get(&key) == Some(&uid) {
+            self.nicks.remove(&key);
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::VecDeque;
+use std::net::Shutdown;
+use std::sync::{Arc, Mutex, Condvar};
+use std::thread;
+use websocket::{Message, Sender};
+
+use client::WsSender;
+
+/// What to do when a client isn't reading its messages fast enough to keep
+/// its outbound queue from filling up.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OverflowPolicy {
+    // forget the oldest queued message to make room
+    DropOldest,
+    // give up on the client
+    Disconnect,
+}
+
+impl OverflowPolicy {
+    pub fn from_name(name: &str) -> Result<Self, ()> {
+        match name {
+            "drop_oldest" => Ok(OverflowPolicy::DropOldest),
+            "disconnect" => Ok(OverflowPolicy::Disconnect),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PushError {
+    // the client has gone away
+    Closed,
+    // the queue was full and the client was disconnected
+    Overflow,
+}
+
+struct State {
+    queue: VecDeque<Arc<String>>,
+    closed: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    cond: Condvar,
+}
+
+/// A bounded queue of messages for one client, drained by a writer thread
+/// of its own so a client with a full TCP window can't hold up the room.
+pub struct Outbox {
+    shared: Arc<Shared>,
+    capacity: usize,
+    overflow: OverflowPolicy,
+}
+
+impl Outbox {
+    pub fn start(sender: WsSender, capacity: usize, overflow: OverflowPolicy) -> Outbox {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                closed: false,
+            }),
+            cond: Condvar::new(),
+        });
+        let writer_shared = shared.clone();
+        thread::spawn(move || writer_thread(writer_shared, sender));
+        Outbox {
+            shared: shared,
+            capacity: capacity,
+            overflow: overflow,
+        }
+    }
+
+    pub fn push(&self, message: Arc<String>) -> Result<(), PushError> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.closed {
+            return Err(PushError::Closed);
+        }
+        if self.capacity <= state.queue.len() {
+            match self.overflow {
+                OverflowPolicy::DropOldest => {
+                    state.queue.pop_front();
+                },
+                OverflowPolicy::Disconnect => {
+                    state.queue.clear();
+                    state.closed = true;
+                    self.shared.cond.notify_one();
+                    return Err(PushError::Overflow);
+                },
+            }
+        }
+        state.queue.push_back(message);
+        self.shared.cond.notify_one();
+        Ok(())
+    }
+
+    /// Discards anything still queued and hangs up on the client.
+    pub fn close(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.queue.clear();
+        state.closed = true;
+        self.shared.cond.notify_one();
+    }
+
+    /// Hangs up on the client once everything already queued has been
+    /// written, so it gets to hear why.
+    pub fn finish(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.closed = true;
+        self.shared.cond.notify_one();
+    }
+}
+
+impl Drop for Outbox {
+    fn drop(&mut self) {
+        // an Outbox which was told to finish gets to do so
+        let finishing = self.shared.state.lock().unwrap().closed;
+        if !finishing {
+            self.close();
+        }
+    }
+}
+
+fn writer_thread(shared: Arc<Shared>, mut sender: WsSender) {
+    loop {
+        let message = {
+            let mut state = shared.state.lock().unwrap();
+            while state.queue.is_empty() && !state.closed {
+                state = shared.cond.wait(state).unwrap();
+            }
+            match state.queue.pop_front() {
+                Some(message) => message,
+                None => break,
+            }
+        };
+        if let Err(err) = sender.send_message(Message::Text((*message).clone())) {
+            info!("dropping a dead client: {:?}", err);
+            break;
+        }
+    }
+
+    shared.state.lock().unwrap().closed = true;
+    let _ = sender.send_message(Message::Close(None));
+    // wakes up the client's reader, which will tell the channel we're gone
+    let _ = sender.get_mut().shutdown(Shutdown::Both);
+}
@@ -0,0 +1,500 @@
+//! Reading and writing playlists as JSON, extended M3U and CSV.
+
+use std::mem;
+use std::u32;
+use serde_json;
+
+use api::{MediaInfo, MediaRef, Playlist, PlaylistFormat, ProviderKind};
+use form;
+
+/// A track as an imported playlist lists it, before it's been looked up.
+pub struct Entry {
+    // where it was in the data: the line for M3U and CSV, the item for
+    // JSON, counting from 1
+    pub line: usize,
+    // the track as the data gave it
+    pub location: String,
+    // Err says why the location couldn't be made sense of
+    pub media: Result<MediaRef, String>,
+    // what the data says about the track, if anything
+    pub claimed: Option<MediaInfo>,
+}
+
+/// An imported playlist.
+pub struct Parsed {
+    pub name: Option<String>,
+    pub entries: Vec<Entry>,
+}
+
+/// How a track is written in M3U and CSV: as a link to it, or as its id
+/// for tracks in the library.
+pub fn location(media: &MediaRef) -> String {
+    match media.provider {
+        ProviderKind::Youtube => format!("https://www.youtube.com/watch?v={}", media.id),
+        ProviderKind::Soundcloud | ProviderKind::Url | ProviderKind::Library => media.id.clone(),
+    }
+}
+
+fn media_ref(provider: ProviderKind, id: &str) -> MediaRef {
+    MediaRef {
+        provider: provider,
+        id: id.to_string(),
+    }
+}
+
+/// Undoes `location`, also making sense of the other ways YouTube links
+/// are written.
+pub fn from_location(location: &str) -> Result<MediaRef, String> {
+    let rest = if location.starts_with("https://") {
+        &location[8..]
+    } else if location.starts_with("http://") {
+        &location[7..]
+    } else if location.is_empty() {
+        return Err("no track given".to_string());
+    } else {
+        return Ok(media_ref(ProviderKind::Library, location));
+    };
+    let (host, path) = match rest.find('/') {
+        Some(idx) => (&rest[..idx], &rest[idx..]),
+        None => (rest, "/"),
+    };
+    let host = host.to_lowercase();
+    match &host[..] {
+        "youtube.com" | "www.youtube.com" | "m.youtube.com" | "music.youtube.com" => {
+            match form::query_param(path, "v") {
+                Some(id) => Ok(media_ref(ProviderKind::Youtube, &id)),
+                None => Err("not a link to a YouTube video".to_string()),
+            }
+        },
+        "youtu.be" => {
+            let id = path.trim_left_matches('/').split(|c: char| c == '?' || c == '#').next().unwrap_or("");
+            Ok(media_ref(ProviderKind::Youtube, id))
+        },
+        "soundcloud.com" | "www.soundcloud.com" => Ok(media_ref(ProviderKind::Soundcloud, location)),
+        _ => Ok(media_ref(ProviderKind::Url, location)),
+    }
+}
+
+/// Splits "Artist - Title" as M3U players write it.
+fn split_title(text: &str) -> (String, Option<String>) {
+    match text.find(" - ") {
+        Some(idx) => (text[idx + 3..].trim().to_string(), Some(text[..idx].trim().to_string())),
+        None => (text.trim().to_string(), None),
+    }
+}
+
+fn claimed(title: String, artist: Option<String>, duration: u64) -> Option<MediaInfo> {
+    if title.is_empty() && duration == 0 {
+        return None;
+    }
+    Some(MediaInfo {
+        title: title,
+        artist: artist.and_then(|a| if a.is_empty() { None } else { Some(a) }),
+        duration: duration,
+        thumbnail: None,
+        stream_url: None,
+    })
+}
+
+// seconds, maybe with a fraction, as M3U and CSV give durations. Casting
+// a float too big for the integer it's cast to is undefined, so they're
+// capped first; anything that long is refused when it's looked up anyway.
+fn parse_seconds(text: &str) -> u64 {
+    match text.trim().parse::<f64>() {
+        Ok(secs) if secs.is_finite() && 0.0 < secs => (secs.min(u32::MAX as f64) * 1000.0) as u64,
+        _ => 0,
+    }
+}
+
+/// Reads a playlist written in `format`. Only JSON which isn't a playlist
+/// at all is refused; tracks which can't be made sense of are returned
+/// with their `media` giving the reason.
+pub fn parse(format: PlaylistFormat, data: &str) -> Result<Parsed, String> {
+    // some editors start files with a byte order mark
+    let data = data.trim_left_matches('\u{feff}');
+    match format {
+        PlaylistFormat::Json => parse_json(data),
+        PlaylistFormat::M3u => Ok(parse_m3u(data)),
+        PlaylistFormat::Csv => Ok(parse_csv(data)),
+    }
+}
+
+#[derive(Deserialize)]
+struct ImportedItem {
+    media: MediaRef,
+    info: Option<MediaInfo>,
+}
+
+/// A `Playlist`, less what only makes sense on the server it came from.
+#[derive(Deserialize)]
+struct ImportedPlaylist {
+    name: Option<String>,
+    items: Vec<ImportedItem>,
+}
+
+fn parse_json(data: &str) -> Result<Parsed, String> {
+    let playlist: ImportedPlaylist = try!(serde_json::from_str(data).map_err(|e| e.to_string()));
+    let entries = playlist.items.into_iter()
+        .enumerate()
+        .map(|(idx, item)| Entry {
+            line: idx + 1,
+            location: location(&item.media),
+            media: Ok(item.media),
+            claimed: item.info,
+        })
+        .collect();
+    Ok(Parsed {
+        name: playlist.name,
+        entries: entries,
+    })
+}
+
+fn parse_m3u(data: &str) -> Parsed {
+    let mut parsed = Parsed {
+        name: None,
+        entries: Vec::new(),
+    };
+    // the #EXTINF line describing the next track, if any
+    let mut extinf = None;
+    for (idx, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with("#PLAYLIST:") {
+            parsed.name = Some(line[10..].trim().to_string());
+        } else if line.starts_with("#EXTINF:") {
+            // #EXTINF:<seconds>[ attributes],<artist - title>
+            let info = &line[8..];
+            let (duration, title) = match info.find(',') {
+                Some(comma) => (&info[..comma], &info[comma + 1..]),
+                None => (info, ""),
+            };
+            let duration = parse_seconds(duration.split(' ').next().unwrap_or(""));
+            let (title, artist) = split_title(title);
+            extinf = claimed(title, artist, duration);
+        } else if !line.is_empty() && !line.starts_with('#') {
+            parsed.entries.push(Entry {
+                line: idx + 1,
+                location: line.to_string(),
+                media: from_location(line),
+                claimed: extinf.take(),
+            });
+        }
+    }
+    parsed
+}
+
+/// Splits CSV into records, with the line each one starts on. Fields may
+/// be quoted, with `""` for a quote, and span lines.
+fn csv_records(data: &str) -> Vec<(usize, Vec<String>)> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut start = 1;
+    let mut chars = data.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            if c == '"' {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    quoted = false;
+                }
+            } else {
+                if c == '\n' {
+                    line += 1;
+                }
+                field.push(c);
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => quoted = true,
+            ',' => record.push(mem::replace(&mut field, String::new())),
+            '\r' => (),
+            '\n' => {
+                record.push(mem::replace(&mut field, String::new()));
+                let done = mem::replace(&mut record, Vec::new());
+                // blank lines aren't records
+                if !(done.len() == 1 && done[0].is_empty()) {
+                    records.push((start, done));
+                }
+                line += 1;
+                start = line;
+            },
+            _ => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push((start, record));
+    }
+    records
+}
+
+// the first of the columns called any of `names`
+fn column(header: &[String], names: &[&str]) -> Option<usize> {
+    header.iter().position(|h| names.iter().any(|n| h == n))
+}
+
+fn field(record: &[String], col: Option<usize>) -> &str {
+    col.and_then(|col| record.get(col)).map_or("", |value| value.trim())
+}
+
+/// Reads CSV with a header row naming its columns. Without one, each
+/// record is taken to start with the track's location.
+fn parse_csv(data: &str) -> Parsed {
+    let mut parsed = Parsed {
+        name: None,
+        entries: Vec::new(),
+    };
+    let records = csv_records(data);
+    let header: Vec<String> = match records.first() {
+        Some(&(_, ref header)) => header.iter().map(|h| h.trim().to_lowercase()).collect(),
+        None => return parsed,
+    };
+    // without a column saying where the tracks are, the first record is
+    // a track too
+    let has_header = column(&header, &["provider", "id", "url", "location"]).is_some();
+    let (header, skip) = if has_header { (header, 1) } else { (vec!["url".to_string()], 0) };
+    let provider_col = column(&header, &["provider"]);
+    let id_col = column(&header, &["id"]);
+    let url_col = column(&header, &["url", "location"]);
+    let title_col = column(&header, &["title"]);
+    let artist_col = column(&header, &["artist"]);
+    let duration_ms_col = column(&header, &["duration_ms"]);
+    let duration_col = column(&header, &["duration"]);
+
+    for (line, record) in records.into_iter().skip(skip) {
+        let (location, media) = match (provider_col, id_col) {
+            (Some(_), Some(_)) if !field(&record, provider_col).is_empty() => {
+                let provider = field(&record, provider_col);
+                let id = field(&record, id_col);
+                let media = ProviderKind::from_name(provider)
+                    .map(|kind| media_ref(kind, id))
+                    .map_err(|_| format!("no such provider as {:?}", provider));
+                (format!("{}:{}", provider, id), media)
+            },
+            _ => {
+                let location = field(&record, url_col);
+                (location.to_string(), from_location(location))
+            },
+        };
+        let duration = match field(&record, duration_ms_col).parse::<u64>() {
+            Ok(ms) => ms,
+            Err(_) => parse_seconds(field(&record, duration_col)),
+        };
+        let artist = field(&record, artist_col).to_string();
+        parsed.entries.push(Entry {
+            line: line,
+            location: location,
+            media: media,
+            claimed: claimed(field(&record, title_col).to_string(), Some(artist), duration),
+        });
+    }
+    parsed
+}
+
+/// Writes out `playlist` in `format`.
+pub fn write(format: PlaylistFormat, playlist: &Playlist) -> String {
+    match format {
+        PlaylistFormat::Json => serde_json::to_string_pretty(playlist).unwrap(),
+        PlaylistFormat::M3u => write_m3u(playlist),
+        PlaylistFormat::Csv => write_csv(playlist),
+    }
+}
+
+// M3U has no way of quoting line breaks
+fn one_line(text: &str) -> String {
+    text.chars().map(|c| if c == '\r' || c == '\n' { ' ' } else { c }).collect()
+}
+
+fn write_m3u(playlist: &Playlist) -> String {
+    let mut out = String::from("#EXTM3U\n");
+    out.push_str(&format!("#PLAYLIST:{}\n", one_line(&playlist.name)));
+    for item in playlist.items.iter() {
+        let title = match item.info.artist {
+            Some(ref artist) => format!("{} - {}", artist, item.info.title),
+            None => item.info.title.clone(),
+        };
+        out.push_str(&format!("#EXTINF:{},{}\n", item.info.duration / 1000, one_line(&title)));
+        out.push_str(&one_line(&location(&item.media)));
+        out.push('\n');
+    }
+    out
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains(|c: char| c == ',' || c == '"' || c == '\r' || c == '\n') {
+        format!("\"{}\"", value.replace("\"", "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn write_csv(playlist: &Playlist) -> String {
+    let mut out = String::from("provider,id,title,artist,duration_ms\r\n");
+    for item in playlist.items.iter() {
+        let fields = [
+            item.media.provider.name().to_string(),
+            item.media.id.clone(),
+            item.info.title.clone(),
+            item.info.artist.clone().unwrap_or(String::new()),
+            item.info.duration.to_string(),
+        ];
+        let fields: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+        out.push_str(&fields.join(","));
+        out.push_str("\r\n");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use api::{MediaInfo, MediaRef, Playlist, PlaylistFormat, PlaylistItem, ProviderKind};
+    use super::{Entry, from_location, parse, parse_csv, parse_m3u, parse_seconds, write_csv, write_m3u};
+
+    fn item(provider: ProviderKind, id: &str, title: &str, artist: Option<&str>, duration: u64) -> PlaylistItem {
+        PlaylistItem {
+            media: MediaRef {
+                provider: provider,
+                id: id.to_string(),
+            },
+            info: MediaInfo {
+                title: title.to_string(),
+                artist: artist.map(|artist| artist.to_string()),
+                duration: duration,
+                thumbnail: None,
+                stream_url: None,
+            },
+        }
+    }
+
+    fn playlist(items: Vec<PlaylistItem>) -> Playlist {
+        Playlist {
+            id: 1,
+            name: "Warm up".to_string(),
+            items: items,
+        }
+    }
+
+    fn youtube(id: &str) -> Result<MediaRef, String> {
+        Ok(MediaRef {
+            provider: ProviderKind::Youtube,
+            id: id.to_string(),
+        })
+    }
+
+    // checks that `entry` gives back `item`
+    fn assert_entry(entry: &Entry, item: &PlaylistItem) {
+        assert_eq!(entry.media, Ok(item.media.clone()));
+        let claimed = entry.claimed.as_ref().unwrap();
+        assert_eq!(claimed.title, item.info.title);
+        assert_eq!(claimed.artist, item.info.artist);
+        assert_eq!(claimed.duration, item.info.duration);
+    }
+
+    #[test]
+    fn csv_round_trips() {
+        let items = vec![
+            item(ProviderKind::Youtube, "dQw4w9WgXcQ", "Intro, \"live\"\nencore", Some("The Band"), 212345),
+            item(ProviderKind::Url, "http://example.com/a.mp3", "A", None, 1000),
+            item(ProviderKind::Library, "albums/intro.flac", "Intro", Some("Somebody"), 61000),
+        ];
+        let parsed = parse_csv(&write_csv(&playlist(items.clone())));
+        assert_eq!(parsed.entries.len(), 3);
+        for (entry, item) in parsed.entries.iter().zip(items.iter()) {
+            assert_entry(entry, item);
+        }
+        // the first track's title takes up two lines
+        let lines: Vec<usize> = parsed.entries.iter().map(|entry| entry.line).collect();
+        assert_eq!(lines, vec![2, 4, 5]);
+    }
+
+    #[test]
+    fn m3u_round_trips() {
+        let items = vec![
+            item(ProviderKind::Youtube, "dQw4w9WgXcQ", "Intro", Some("The Band"), 212000),
+            item(ProviderKind::Soundcloud, "https://soundcloud.com/someone/something", "Something", None, 90000),
+            item(ProviderKind::Library, "albums/intro.flac", "Intro", None, 61000),
+        ];
+        let parsed = parse_m3u(&write_m3u(&playlist(items.clone())));
+        assert_eq!(parsed.name, Some("Warm up".to_string()));
+        assert_eq!(parsed.entries.len(), 3);
+        for (entry, item) in parsed.entries.iter().zip(items.iter()) {
+            assert_entry(entry, item);
+        }
+        let lines: Vec<usize> = parsed.entries.iter().map(|entry| entry.line).collect();
+        assert_eq!(lines, vec![4, 6, 8]);
+    }
+
+    #[test]
+    fn json_round_trips() {
+        let items = vec![item(ProviderKind::Youtube, "dQw4w9WgXcQ", "Intro", Some("The Band"), 212345)];
+        let data = serde_json::to_string(&playlist(items.clone())).unwrap();
+        let parsed = parse(PlaylistFormat::Json, &data).unwrap();
+        assert_eq!(parsed.name, Some("Warm up".to_string()));
+        assert_eq!(parsed.entries.len(), 1);
+        assert_entry(&parsed.entries[0], &items[0]);
+        assert!(parse(PlaylistFormat::Json, "[1, 2, 3]").is_err());
+    }
+
+    #[test]
+    fn csv_fields_may_be_quoted_across_lines() {
+        let data = "url,title\n\"https://youtu.be/dQw4w9WgXcQ\",\"one\ntwo, \"\"three\"\"\"\nhttps://youtu.be/abcdefghijk,four\n";
+        let parsed = parse_csv(data);
+        assert_eq!(parsed.entries.len(), 2);
+        assert_eq!(parsed.entries[0].media, youtube("dQw4w9WgXcQ"));
+        assert_eq!(parsed.entries[0].claimed.as_ref().unwrap().title, "one\ntwo, \"three\"");
+        assert_eq!(parsed.entries[1].line, 4);
+        assert_eq!(parsed.entries[1].claimed.as_ref().unwrap().title, "four");
+    }
+
+    #[test]
+    fn csv_without_a_header_is_a_list_of_links() {
+        let parsed = parse_csv("https://youtu.be/dQw4w9WgXcQ\nhttp://example.com/a.mp3\n");
+        assert_eq!(parsed.entries.len(), 2);
+        assert_eq!(parsed.entries[0].line, 1);
+        assert_eq!(parsed.entries[0].media, youtube("dQw4w9WgXcQ"));
+        assert_eq!(parsed.entries[1].media, Ok(MediaRef {
+            provider: ProviderKind::Url,
+            id: "http://example.com/a.mp3".to_string(),
+        }));
+    }
+
+    #[test]
+    fn byte_order_marks_are_skipped() {
+        let parsed = parse(PlaylistFormat::Csv, "\u{feff}url\nhttps://youtu.be/dQw4w9WgXcQ\n").unwrap();
+        assert_eq!(parsed.entries.len(), 1);
+        assert_eq!(parsed.entries[0].media, youtube("dQw4w9WgXcQ"));
+        let parsed = parse(PlaylistFormat::M3u, "\u{feff}#EXTM3U\n#PLAYLIST:Warm up\n").unwrap();
+        assert_eq!(parsed.name, Some("Warm up".to_string()));
+        let parsed = parse(PlaylistFormat::Json, "\u{feff}{\"name\": null, \"items\": []}").unwrap();
+        assert!(parsed.entries.is_empty());
+    }
+
+    #[test]
+    fn youtube_links_come_in_several_forms() {
+        assert_eq!(from_location("https://youtu.be/dQw4w9WgXcQ"), youtube("dQw4w9WgXcQ"));
+        assert_eq!(from_location("https://youtu.be/dQw4w9WgXcQ?t=42"), youtube("dQw4w9WgXcQ"));
+        assert_eq!(from_location("https://www.youtube.com/watch?v=dQw4w9WgXcQ"), youtube("dQw4w9WgXcQ"));
+        assert_eq!(from_location("http://m.youtube.com/watch?feature=share&v=dQw4w9WgXcQ"), youtube("dQw4w9WgXcQ"));
+        assert_eq!(from_location("https://YouTube.com/watch?v=dQw4w9WgXcQ&list=abc"), youtube("dQw4w9WgXcQ"));
+        assert!(from_location("https://www.youtube.com/channel/abc").is_err());
+        assert!(from_location("").is_err());
+    }
+
+    #[test]
+    fn durations_in_seconds_are_capped() {
+        assert_eq!(parse_seconds("3.5"), 3500);
+        assert_eq!(parse_seconds(" 212 "), 212000);
+        assert_eq!(parse_seconds("1e300"), 4294967295000);
+        assert_eq!(parse_seconds("inf"), 0);
+        assert_eq!(parse_seconds("NaN"), 0);
+        assert_eq!(parse_seconds("-5"), 0);
+        assert_eq!(parse_seconds("soon"), 0);
+    }
+}
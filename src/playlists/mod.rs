@@ -0,0 +1,380 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use rand::{self, Rng};
+use serde_json;
+
+use api::{ImportFailure, ImportReport, Playlist, PlaylistFormat, PlaylistItem};
+use form;
+use media::Providers;
+
+pub mod formats;
+
+// longest playlist name accepted, in characters
+const MAX_NAME_LEN: usize = 64;
+
+#[derive(Debug)]
+pub enum PlaylistError {
+    Invalid(String),
+    NotFound(String),
+    LimitReached(String),
+}
+
+impl fmt::Display for PlaylistError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PlaylistError::Invalid(ref msg) => write!(f, "invalid: {}", msg),
+            PlaylistError::NotFound(ref msg) => write!(f, "not found: {}", msg),
+            PlaylistError::LimitReached(ref msg) => write!(f, "limit reached: {}", msg),
+        }
+    }
+}
+
+/// Everything one user has saved.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Crate {
+    // the last playlist id handed out
+    pub last_id: u64,
+    pub active: Option<u64>,
+    pub playlists: Vec<Playlist>,
+}
+
+impl Crate {
+    fn playlist(&mut self, id: u64) -> Result<&mut Playlist, PlaylistError> {
+        self.playlists.iter_mut()
+            .find(|p| p.id == id)
+            .ok_or(PlaylistError::NotFound("no such playlist".to_string()))
+    }
+}
+
+fn check_name(name: &str) -> Result<String, PlaylistError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(PlaylistError::Invalid("playlists need a name".to_string()));
+    }
+    if MAX_NAME_LEN < name.chars().count() {
+        let msg = format!("playlist names may be at most {} characters", MAX_NAME_LEN);
+        return Err(PlaylistError::Invalid(msg));
+    }
+    Ok(name.to_string())
+}
+
+fn check_index(playlist: &Playlist, index: usize) -> Result<(), PlaylistError> {
+    if playlist.items.len() <= index {
+        return Err(PlaylistError::NotFound("no such track in the playlist".to_string()));
+    }
+    Ok(())
+}
+
+/// A playlist to be imported.
+pub struct Import {
+    pub format: PlaylistFormat,
+    pub data: String,
+    // the name to give it, rather than the one in the data
+    pub name: Option<String>,
+    // only report what would be imported
+    pub dry_run: bool,
+    // longest track accepted, in milliseconds
+    pub max_duration: u64,
+}
+
+/// The playlists of every user, by nick_key, shared between rooms. With a
+/// directory to keep them in, each user's are saved to a file of their own
+/// whenever they change; without, they last until the server stops.
+pub struct Store {
+    dir: Option<PathBuf>,
+    max_playlists: usize,
+    max_len: usize,
+    crates: Mutex<HashMap<String, Crate>>,
+}
+
+impl Store {
+    pub fn new(dir: Option<PathBuf>, max_playlists: usize, max_len: usize) -> Store {
+        Store {
+            dir: dir,
+            max_playlists: max_playlists,
+            max_len: max_len,
+            crates: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// A store with the same limits as this one which keeps nothing on
+    /// disk.
+    pub fn scratch(&self) -> Store {
+        Store::new(None, self.max_playlists, self.max_len)
+    }
+
+    fn path(&self, owner: &str) -> Option<PathBuf> {
+        self.dir.as_ref().map(|dir| dir.join(format!("{}.json", form::encode(owner))))
+    }
+
+    fn load(&self, owner: &str) -> Crate {
+        let path = match self.path(owner) {
+            Some(path) => path,
+            None => return Crate::default(),
+        };
+        let mut text = String::new();
+        match File::open(&path).and_then(|mut f| f.read_to_string(&mut text)) {
+            Ok(_) => (),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Crate::default(),
+            Err(err) => {
+                warn!("unable to read {}: {}", path.display(), err);
+                return Crate::default();
+            }
+        }
+        match serde_json::from_str(&text) {
+            Ok(saved) => saved,
+            Err(err) => {
+                warn!("unable to parse {}: {}", path.display(), err);
+                Crate::default()
+            }
+        }
+    }
+
+    fn save(&self, owner: &str, saved: &Crate) -> io::Result<()> {
+        let path = match self.path(owner) {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        try!(fs::create_dir_all(path.parent().unwrap()));
+        // written aside and renamed into place, so a crash mid-write
+        // leaves the old playlists intact
+        let partial = path.with_extension("json.partial");
+        {
+            let mut file = try!(File::create(&partial));
+            try!(file.write_all(serde_json::to_string(saved).unwrap().as_bytes()));
+        }
+        fs::rename(&partial, &path)
+    }
+
+    /// Runs `f` on `owner`'s playlists, saving them if it succeeds.
+    fn update<T, F>(&self, owner: &str, f: F) -> Result<T, PlaylistError>
+        where F: FnOnce(&mut Crate) -> Result<T, PlaylistError>
+    {
+        let mut crates = self.crates.lock().unwrap();
+        let saved = crates.entry(owner.to_string()).or_insert_with(|| self.load(owner));
+        let result = try!(f(saved));
+        if let Err(err) = self.save(owner, saved) {
+            warn!("unable to save playlists of {:?}: {}", owner, err);
+        }
+        Ok(result)
+    }
+
+    pub fn get(&self, owner: &str) -> Crate {
+        let mut crates = self.crates.lock().unwrap();
+        crates.entry(owner.to_string()).or_insert_with(|| self.load(owner)).clone()
+    }
+
+    /// Adds a playlist to `owner`'s, returning its id.
+    fn insert(&self, owner: &str, name: String, items: Vec<PlaylistItem>) -> Result<u64, PlaylistError> {
+        let max_playlists = self.max_playlists;
+        self.update(owner, |saved| {
+            if max_playlists <= saved.playlists.len() {
+                return Err(PlaylistError::LimitReached("you have too many playlists".to_string()));
+            }
+            saved.last_id += 1;
+            let id = saved.last_id;
+            saved.playlists.push(Playlist {
+                id: id,
+                name: name,
+                items: items,
+            });
+            // the first playlist is the one played from
+            if saved.active.is_none() {
+                saved.active = Some(id);
+            }
+            Ok(id)
+        })
+    }
+
+    /// Makes a new, empty playlist, returning its id.
+    pub fn create(&self, owner: &str, name: &str) -> Result<u64, PlaylistError> {
+        let name = try!(check_name(name));
+        self.insert(owner, name, Vec::new())
+    }
+
+    pub fn rename(&self, owner: &str, id: u64, name: &str) -> Result<(), PlaylistError> {
+        let name = try!(check_name(name));
+        self.update(owner, |saved| {
+            try!(saved.playlist(id)).name = name;
+            Ok(())
+        })
+    }
+
+    pub fn delete(&self, owner: &str, id: u64) -> Result<(), PlaylistError> {
+        self.update(owner, |saved| {
+            let before = saved.playlists.len();
+            saved.playlists.retain(|p| p.id != id);
+            if saved.playlists.len() == before {
+                return Err(PlaylistError::NotFound("no such playlist".to_string()));
+            }
+            if saved.active == Some(id) {
+                saved.active = None;
+            }
+            Ok(())
+        })
+    }
+
+    pub fn add(&self, owner: &str, id: u64, item: PlaylistItem) -> Result<(), PlaylistError> {
+        let max_len = self.max_len;
+        self.update(owner, |saved| {
+            let playlist = try!(saved.playlist(id));
+            if max_len <= playlist.items.len() {
+                return Err(PlaylistError::LimitReached("the playlist is full".to_string()));
+            }
+            playlist.items.push(item);
+            Ok(())
+        })
+    }
+
+    pub fn remove(&self, owner: &str, id: u64, index: usize) -> Result<(), PlaylistError> {
+        self.update(owner, |saved| {
+            let playlist = try!(saved.playlist(id));
+            try!(check_index(playlist, index));
+            playlist.items.remove(index);
+            Ok(())
+        })
+    }
+
+    /// Moves the track at `from` to `to`, or to the end if `to` is past it.
+    pub fn move_item(&self, owner: &str, id: u64, from: usize, to: usize) -> Result<(), PlaylistError> {
+        self.update(owner, |saved| {
+            let playlist = try!(saved.playlist(id));
+            try!(check_index(playlist, from));
+            let item = playlist.items.remove(from);
+            let to = cmp::min(to, playlist.items.len());
+            playlist.items.insert(to, item);
+            Ok(())
+        })
+    }
+
+    pub fn shuffle(&self, owner: &str, id: u64) -> Result<(), PlaylistError> {
+        self.update(owner, |saved| {
+            let playlist = try!(saved.playlist(id));
+            rand::thread_rng().shuffle(&mut playlist.items);
+            Ok(())
+        })
+    }
+
+    pub fn activate(&self, owner: &str, id: Option<u64>) -> Result<(), PlaylistError> {
+        self.update(owner, |saved| {
+            if let Some(id) = id {
+                try!(saved.playlist(id));
+            }
+            saved.active = id;
+            Ok(())
+        })
+    }
+
+    /// Makes a playlist for `owner` out of the tracks in `import`, looking
+    /// each of them up, which may take a while. The report's `when` is left
+    /// for the caller to fill in.
+    pub fn import(&self, providers: &Providers, owner: &str, import: &Import) -> Result<ImportReport, PlaylistError> {
+        let parsed = try!(formats::parse(import.format, &import.data).map_err(PlaylistError::Invalid));
+        let name = try!(check_name(import.name.as_ref()
+            .or(parsed.name.as_ref())
+            .map_or("Imported", |name| &name[..])));
+        // better to say so before looking up hundreds of tracks
+        if !import.dry_run && self.max_playlists <= self.get(owner).playlists.len() {
+            return Err(PlaylistError::LimitReached("you have too many playlists".to_string()));
+        }
+
+        let tracks = parsed.entries.len();
+        // every entry may cost a lookup, failing ones included, so there's
+        // only room for so many more than will fit in the playlist
+        if 2 * self.max_len < tracks {
+            let msg = format!("playlists may have at most {} tracks", self.max_len);
+            return Err(PlaylistError::LimitReached(msg));
+        }
+        let mut items = Vec::new();
+        let mut failed = Vec::new();
+        for entry in parsed.entries.into_iter() {
+            let formats::Entry { line, location, media, claimed } = entry;
+            let result = if self.max_len <= items.len() {
+                Err("the playlist is full".to_string())
+            } else {
+                media.and_then(|media| {
+                    let info = try!(providers.resolve(&media, claimed.as_ref()).map_err(|e| e.to_string()));
+                    if import.max_duration < info.duration {
+                        return Err("longer than tracks may be".to_string());
+                    }
+                    Ok(PlaylistItem {
+                        media: media,
+                        info: info,
+                    })
+                })
+            };
+            match result {
+                Ok(item) => items.push(item),
+                Err(reason) => failed.push(ImportFailure {
+                    line: line,
+                    location: location,
+                    reason: reason,
+                }),
+            }
+        }
+
+        let imported = items.len();
+        let playlist = if import.dry_run {
+            None
+        } else {
+            Some(try!(self.insert(owner, name.clone(), items)))
+        };
+        Ok(ImportReport {
+            when: 0,
+            dry_run: import.dry_run,
+            playlist: playlist,
+            name: name,
+            tracks: tracks,
+            imported: imported,
+            failed: failed,
+        })
+    }
+
+    /// Writes out one of `owner`'s playlists in `format`.
+    pub fn export(&self, owner: &str, id: u64, format: PlaylistFormat) -> Result<String, PlaylistError> {
+        let saved = self.get(owner);
+        match saved.playlists.iter().find(|p| p.id == id) {
+            Some(playlist) => Ok(formats::write(format, playlist)),
+            None => Err(PlaylistError::NotFound("no such playlist".to_string())),
+        }
+    }
+
+    /// Whether `owner` has a track to play in their active playlist, of at
+    /// most `max_duration` milliseconds.
+    pub fn has_next(&self, owner: &str, max_duration: u64) -> bool {
+        let saved = self.get(owner);
+        saved.active.map_or(false, |id| saved.playlists.iter().any(|p| {
+            p.id == id && p.items.iter().any(|item| item.info.duration <= max_duration)
+        }))
+    }
+
+    /// Takes the first track of at most `max_duration` milliseconds from
+    /// the front of `owner`'s active playlist, moving it and any longer
+    /// ones it passed over to the back. Playlists are shared by rooms whose
+    /// limits differ, so a track may be fine for one room but not another.
+    pub fn next_track(&self, owner: &str, max_duration: u64) -> Option<PlaylistItem> {
+        self.update(owner, |saved| {
+            let id = try!(saved.active.ok_or(PlaylistError::NotFound("no active playlist".to_string())));
+            let playlist = try!(saved.playlist(id));
+            let idx = try!(playlist.items.iter()
+                .position(|item| item.info.duration <= max_duration)
+                .ok_or(PlaylistError::NotFound("nothing in the playlist is short enough".to_string())));
+            for _ in 0..idx + 1 {
+                let item = playlist.items.remove(0);
+                playlist.items.push(item);
+            }
+            Ok(playlist.items[playlist.items.len() - 1].clone())
+        }).ok()
+    }
+
+    /// Lets go of `owner`'s playlists, which are loaded again if they're
+    /// needed. A store without a directory forgets them for good.
+    pub fn forget(&self, owner: &str) {
+        self.crates.lock().unwrap().remove(owner);
+    }
+}
@@ -1,42 +1,301 @@
-use api::IngressMessage;
+use std::collections::BTreeMap;
 
-pub trait Policy {
-    fn allow(&self, _msg: &IngressMessage) -> bool;
+use api::{ErrorCode, IngressMessage, Role};
+use channel::Rejection;
+use config::RoomConfig;
+use super::UserId;
+
+/// What a policy gets to look at when deciding on a request, besides the
+/// request itself.
+pub struct Context<'a> {
+    // the user making the request
+    pub actor: UserId,
+    pub role: Role,
+    // the user the request acts on, and their role, if it acts on one
+    // who's in the room or on a mute or ban they're under
+    pub target: Option<(UserId, Role)>,
+    // the role of the staff who placed the mute or ban an unmute or unban
+    // would lift, unless the room placed it
+    pub sanctioned_by: Option<Role>,
+    // the current DJ, if anything is playing
+    pub dj: Option<UserId>,
+    // when the actor may chat again, if they've been muted
+    pub muted_until: Option<u64>,
+    // when the actor last chatted
+    pub last_message: Option<u64>,
+    // milliseconds since the unix epoch, as used in `when` fields
+    pub now: u64,
+    pub config: &'a RoomConfig,
 }
 
-pub const ANONYMOUS: &'static Policy = &AnonymousPolicy;
+pub enum Decision {
+    Allow,
+    Deny(Rejection),
+}
 
-#[derive(Clone)]
-pub struct AnonymousPolicy;
+impl Decision {
+    fn deny(code: ErrorCode, message: &str) -> Decision {
+        Decision::Deny(Rejection::new(code, message))
+    }
+}
 
-impl Policy for AnonymousPolicy {
-    fn allow(&self, msg: &IngressMessage) -> bool {
-        match *msg {
-            IngressMessage::Register(_) => true,
-            IngressMessage::Part => true,
-            _ => false,
+impl<'a> Context<'a> {
+    /// Whether slow mode says the actor must wait before chatting again.
+    fn slowed(&self) -> bool {
+        let wait = self.config.slow_mode as u64 * 1000;
+        match self.last_message {
+            Some(last) => self.now < last + wait,
+            None => false,
         }
     }
+
+    /// Whether the target of the request, if any, is of the actor's role or
+    /// above.
+    fn outranked(&self) -> bool {
+        match self.target {
+            Some((_, role)) => self.role <= role,
+            None => false,
+        }
+    }
+}
+
+pub trait Policy {
+    fn decide(&self, ctx: &Context, msg: &IngressMessage) -> Decision;
+}
+
+/// The least role needed for each kind of request, unless a room's config
+/// says otherwise. Every kind of `IngressMessage` is listed here.
+const DEFAULT_PERMISSIONS: &'static [(&'static str, Role)] = &[
+    ("register", Role::Guest),
+    ("authenticate", Role::Guest),
+    ("disconnect", Role::Guest),
+    ("part", Role::Guest),
+    ("clock", Role::Guest),
+    ("dj_queue", Role::User),
+    ("dj_unqueue", Role::User),
+    ("enqueue", Role::User),
+    ("dequeue", Role::User),
+    ("reorder", Role::User),
+    ("message", Role::User),
+    ("nick", Role::User),
+    ("vote", Role::User),
+    ("grab", Role::User),
+    ("playlists", Role::User),
+    ("playlist_create", Role::User),
+    ("playlist_rename", Role::User),
+    ("playlist_delete", Role::User),
+    ("playlist_add", Role::User),
+    ("playlist_remove", Role::User),
+    ("playlist_move", Role::User),
+    ("playlist_shuffle", Role::User),
+    ("playlist_activate", Role::User),
+    ("playlist_import", Role::User),
+    ("playlist_export", Role::User),
+    ("history", Role::Guest),
+    // skipping someone else's track; DJs may always skip their own
+    ("skip", Role::Bouncer),
+    ("set_role", Role::Bouncer),
+    ("kick", Role::Bouncer),
+    ("mute", Role::Bouncer),
+    ("unmute", Role::Bouncer),
+    ("ban", Role::Manager),
+    ("unban", Role::Manager),
+];
+
+/// Whether `kind` is the wire name of a request.
+pub fn is_request_kind(kind: &str) -> bool {
+    DEFAULT_PERMISSIONS.iter().any(|&(k, _)| k == kind)
 }
 
-#[derive(Clone)]
-pub struct DefaultPolicy;
+/// A room's permission matrix: which role each kind of request needs.
+#[derive(Clone, Debug)]
+pub struct Permissions {
+    required: BTreeMap<String, Role>,
+}
 
-impl Policy for DefaultPolicy {
-    fn allow(&self, msg: &IngressMessage) -> bool {
+impl Permissions {
+    pub fn new() -> Permissions {
+        let required = DEFAULT_PERMISSIONS.iter()
+            .map(|&(kind, role)| (kind.to_string(), role))
+            .collect();
+        Permissions { required: required }
+    }
+
+    /// Changes the role needed for requests of `kind`, which must be the
+    /// wire name of a request.
+    pub fn set(&mut self, kind: &str, role: Role) -> Result<(), ()> {
+        match self.required.get_mut(kind) {
+            Some(required) => {
+                *required = role;
+                Ok(())
+            },
+            None => Err(()),
+        }
+    }
+
+    fn allows(&self, role: Role, kind: &str) -> bool {
+        match self.required.get(kind) {
+            Some(&required) => required <= role,
+            // a request nobody thought to list
+            None => role == Role::Host,
+        }
+    }
+}
+
+impl Policy for Permissions {
+    fn decide(&self, ctx: &Context, msg: &IngressMessage) -> Decision {
         match *msg {
-            IngressMessage::Part => true,
-            IngressMessage::Message(_) => true,
-            _ => false,
+            IngressMessage::Skip if ctx.dj == Some(ctx.actor) => {
+                return Decision::Allow;
+            },
+            IngressMessage::Message(ref body) if ctx.config.max_message_len < body.len() => {
+                return Decision::deny(ErrorCode::TooLarge, "message is too long");
+            },
+            IngressMessage::Message(_) if ctx.muted_until.map_or(false, |until| ctx.now < until) => {
+                return Decision::deny(ErrorCode::Forbidden, "you are muted");
+            },
+            IngressMessage::Message(_) if ctx.role < Role::Bouncer && ctx.slowed() => {
+                return Decision::deny(ErrorCode::RateLimited, "slow mode is on");
+            },
+            IngressMessage::Vote(_) |
+            IngressMessage::Grab if ctx.dj == Some(ctx.actor) => {
+                return Decision::deny(ErrorCode::Forbidden, "can't vote on your own play");
+            },
+            IngressMessage::SetRole(ref req) => {
+                if req.uid == ctx.actor {
+                    return Decision::deny(ErrorCode::Forbidden, "can't change your own role");
+                }
+                if ctx.outranked() || ctx.role <= req.role {
+                    return Decision::deny(ErrorCode::Forbidden,
+                                          "only roles below your own can be changed");
+                }
+            },
+            IngressMessage::Kick(_) |
+            IngressMessage::Mute(_) |
+            IngressMessage::Unmute(_) |
+            IngressMessage::Ban(_) |
+            IngressMessage::Unban(_) if ctx.outranked() => {
+                return Decision::deny(ErrorCode::Forbidden,
+                                      "only users below your own role can be moderated");
+            },
+            IngressMessage::Unmute(_) |
+            IngressMessage::Unban(_) if ctx.sanctioned_by.map_or(false, |by| ctx.role < by) => {
+                return Decision::deny(ErrorCode::Forbidden,
+                                      "only staff of the same role or above can lift that");
+            },
+            _ => (),
+        }
+        if self.allows(ctx.role, msg.kind()) {
+            Decision::Allow
+        } else {
+            Decision::deny(ErrorCode::Forbidden, "not allowed")
         }
     }
 }
 
-#[derive(Clone)]
-pub struct OwnerPolicy;
+#[cfg(test)]
+mod tests {
+    use api::{IngressMessage, KickMessage, Role, SetRoleMessage, TargetMessage};
+    use config::{Config, RoomConfig};
+    use super::*;
+    use super::super::UserId;
 
-impl Policy for OwnerPolicy {
-    fn allow(&self, _msg: &IngressMessage) -> bool {
-        true
+    fn room() -> RoomConfig {
+        Config::default().room_defaults
     }
-}
\ No newline at end of file
+
+    fn context(config: &RoomConfig, role: Role, target: Option<Role>) -> Context {
+        Context {
+            actor: UserId(1),
+            role: role,
+            target: target.map(|role| (UserId(2), role)),
+            sanctioned_by: None,
+            dj: None,
+            muted_until: None,
+            last_message: None,
+            now: 100 * 1000,
+            config: config,
+        }
+    }
+
+    fn allowed(ctx: &Context, msg: &IngressMessage) -> bool {
+        match ctx.config.permissions.decide(ctx, msg) {
+            Decision::Allow => true,
+            Decision::Deny(_) => false,
+        }
+    }
+
+    fn unban() -> IngressMessage {
+        IngressMessage::Unban(TargetMessage { uid: UserId(2) })
+    }
+
+    #[test]
+    fn requests_need_the_role_listed_for_them() {
+        let config = room();
+        let message = IngressMessage::Message("hi".to_string());
+        assert!(!allowed(&context(&config, Role::Guest, None), &message));
+        assert!(allowed(&context(&config, Role::User, None), &message));
+        assert!(!allowed(&context(&config, Role::User, None), &IngressMessage::Skip));
+        assert!(allowed(&context(&config, Role::Bouncer, None), &IngressMessage::Skip));
+
+        let mut config = room();
+        config.permissions.set("message", Role::ResidentDj).unwrap();
+        assert!(config.permissions.set("shout", Role::User).is_err());
+        assert!(!allowed(&context(&config, Role::User, None), &message));
+        assert!(allowed(&context(&config, Role::ResidentDj, None), &message));
+    }
+
+    #[test]
+    fn djs_may_skip_their_own_play_but_not_vote_on_it() {
+        let config = room();
+        let mut ctx = context(&config, Role::User, None);
+        ctx.dj = Some(UserId(1));
+        assert!(allowed(&ctx, &IngressMessage::Skip));
+        assert!(!allowed(&ctx, &IngressMessage::Grab));
+    }
+
+    #[test]
+    fn muted_and_slowed_users_are_kept_quiet() {
+        let mut config = room();
+        config.slow_mode = 10;
+        let message = IngressMessage::Message("hi".to_string());
+
+        let mut ctx = context(&config, Role::User, None);
+        ctx.muted_until = Some(ctx.now + 1);
+        assert!(!allowed(&ctx, &message));
+
+        let mut ctx = context(&config, Role::User, None);
+        ctx.last_message = Some(ctx.now - 5 * 1000);
+        assert!(!allowed(&ctx, &message));
+        ctx.role = Role::Bouncer;
+        assert!(allowed(&ctx, &message));
+    }
+
+    #[test]
+    fn staff_only_act_on_those_below_them() {
+        let config = room();
+        let kick = IngressMessage::Kick(KickMessage { uid: UserId(2), reason: None });
+        assert!(allowed(&context(&config, Role::Bouncer, Some(Role::User)), &kick));
+        assert!(!allowed(&context(&config, Role::Bouncer, Some(Role::Bouncer)), &kick));
+
+        let promote = IngressMessage::SetRole(SetRoleMessage { uid: UserId(2), role: Role::Manager });
+        assert!(!allowed(&context(&config, Role::Manager, Some(Role::User)), &promote));
+        let promote = IngressMessage::SetRole(SetRoleMessage { uid: UserId(2), role: Role::Bouncer });
+        assert!(allowed(&context(&config, Role::Manager, Some(Role::User)), &promote));
+    }
+
+    #[test]
+    fn bans_are_lifted_only_by_staff_as_senior_as_whoever_placed_them() {
+        let mut config = room();
+        config.permissions.set("unban", Role::Bouncer).unwrap();
+
+        let mut ctx = context(&config, Role::Bouncer, Some(Role::User));
+        ctx.sanctioned_by = Some(Role::Host);
+        assert!(!allowed(&ctx, &unban()));
+        ctx.sanctioned_by = Some(Role::Bouncer);
+        assert!(allowed(&ctx, &unban()));
+
+        let ctx = context(&config, Role::Bouncer, Some(Role::Manager));
+        assert!(!allowed(&ctx, &unban()));
+    }
+}
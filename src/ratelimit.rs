@@ -0,0 +1,216 @@
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
+
+/// How often a user may make one kind of request: up to `burst` at once,
+/// then one every `interval` milliseconds.
+#[derive(Copy, Clone, Debug)]
+pub struct RateLimit {
+    pub burst: u64,
+    pub interval: u64,
+}
+
+impl RateLimit {
+    pub fn new(burst: u64, interval: u64) -> RateLimit {
+        RateLimit {
+            burst: burst,
+            interval: interval,
+        }
+    }
+
+    /// The most credit a bucket may hold: `burst` requests' worth, or as
+    /// near as a u64 gets for limits too generous to count that high.
+    fn capacity(&self) -> u64 {
+        self.burst.saturating_mul(self.interval)
+    }
+}
+
+/// A token bucket, kept in milliseconds of credit: each request costs
+/// `interval` and credit comes back with time, up to `burst` requests' worth.
+struct Bucket {
+    credit: u64,
+    last: u64,
+}
+
+impl Bucket {
+    fn take(&mut self, limit: &RateLimit, now: u64) -> bool {
+        let refill = now.saturating_sub(self.last);
+        self.credit = cmp::min(limit.capacity(), self.credit.saturating_add(refill));
+        self.last = now;
+        if limit.interval <= self.credit {
+            self.credit -= limit.interval;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// strikes older than this are forgotten
+const STRIKE_WINDOW: u64 = 60 * 1000;
+
+// automatic mutes stop doubling once they reach a day
+const MAX_MUTE: u64 = 24 * 60 * 60 * 1000;
+
+// a user who makes no requests for this long is forgotten, offences and all
+const IDLE: u64 = 2 * MAX_MUTE;
+
+/// Keeps track of how fast one user is making requests, and how often
+/// they've gone over the limits.
+pub struct Limiter {
+    buckets: HashMap<String, Bucket>,
+    // when the user last chatted, for slow mode
+    pub last_message: Option<u64>,
+    strikes: usize,
+    last_strike: u64,
+    // how many times they've been muted for flooding
+    offences: u32,
+    // when they last made a request
+    last_seen: u64,
+}
+
+impl Limiter {
+    pub fn new() -> Limiter {
+        Limiter {
+            buckets: HashMap::new(),
+            last_message: None,
+            strikes: 0,
+            last_strike: 0,
+            offences: 0,
+            last_seen: 0,
+        }
+    }
+
+    /// Whether the user has gone long enough without making a request for
+    /// their record to be forgotten.
+    pub fn idle(&self, now: u64) -> bool {
+        IDLE < now.saturating_sub(self.last_seen)
+    }
+
+    /// Takes a request of `kind` out of its bucket, if it has a limit.
+    /// Returns false if the user has run out.
+    pub fn allow(&mut self, limits: &BTreeMap<String, RateLimit>, kind: &str, now: u64) -> bool {
+        self.last_seen = now;
+        let limit = match limits.get(kind) {
+            Some(limit) => limit,
+            None => return true,
+        };
+        let bucket = self.buckets.entry(kind.to_string()).or_insert_with(|| Bucket {
+            credit: limit.capacity(),
+            last: now,
+        });
+        bucket.take(limit, now)
+    }
+
+    /// Records a request refused for going over a limit. Once there have
+    /// been `max_strikes` of them in quick succession, returns how long the
+    /// user should be muted for: `mute` milliseconds the first time,
+    /// doubling with each further offence.
+    pub fn strike(&mut self, now: u64, max_strikes: usize, mute: u64) -> Option<u64> {
+        if max_strikes == 0 {
+            return None;
+        }
+        if STRIKE_WINDOW < now.saturating_sub(self.last_strike) {
+            self.strikes = 0;
+        }
+        self.strikes += 1;
+        self.last_strike = now;
+        if self.strikes < max_strikes {
+            return None;
+        }
+        self.strikes = 0;
+        let factor = 1u64 << cmp::min(self.offences, 16);
+        self.offences += 1;
+        Some(cmp::min(MAX_MUTE, mute.saturating_mul(factor)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::u64;
+
+    use super::*;
+    use super::{IDLE, MAX_MUTE, STRIKE_WINDOW};
+
+    fn limits(burst: u64, interval: u64) -> BTreeMap<String, RateLimit> {
+        let mut limits = BTreeMap::new();
+        limits.insert("message".to_string(), RateLimit::new(burst, interval));
+        limits
+    }
+
+    #[test]
+    fn bursts_are_allowed_then_refused() {
+        let limits = limits(3, 1000);
+        let mut limiter = Limiter::new();
+        for _ in 0..3 {
+            assert!(limiter.allow(&limits, "message", 5000));
+        }
+        assert!(!limiter.allow(&limits, "message", 5000));
+    }
+
+    #[test]
+    fn credit_comes_back_with_time() {
+        let limits = limits(2, 1000);
+        let mut limiter = Limiter::new();
+        assert!(limiter.allow(&limits, "message", 5000));
+        assert!(limiter.allow(&limits, "message", 5000));
+        assert!(!limiter.allow(&limits, "message", 5999));
+        assert!(limiter.allow(&limits, "message", 6000));
+        assert!(!limiter.allow(&limits, "message", 6000));
+
+        // however long it's been, only a burst's worth comes back
+        assert!(limiter.allow(&limits, "message", 60000));
+        assert!(limiter.allow(&limits, "message", 60000));
+        assert!(!limiter.allow(&limits, "message", 60000));
+    }
+
+    #[test]
+    fn requests_without_a_limit_are_always_allowed() {
+        let limits = limits(1, 1000);
+        let mut limiter = Limiter::new();
+        for _ in 0..100 {
+            assert!(limiter.allow(&limits, "vote", 5000));
+        }
+    }
+
+    #[test]
+    fn huge_limits_do_not_overflow() {
+        let limits = limits(u64::MAX, u64::MAX / 2);
+        let mut limiter = Limiter::new();
+        assert!(limiter.allow(&limits, "message", 5000));
+        assert!(limiter.allow(&limits, "message", u64::MAX));
+    }
+
+    #[test]
+    fn repeated_strikes_mute_for_longer_each_time() {
+        let mut limiter = Limiter::new();
+        assert_eq!(limiter.strike(1000, 3, 60000), None);
+        assert_eq!(limiter.strike(2000, 3, 60000), None);
+        assert_eq!(limiter.strike(3000, 3, 60000), Some(60000));
+        assert_eq!(limiter.strike(4000, 3, 60000), None);
+        assert_eq!(limiter.strike(5000, 3, 60000), None);
+        assert_eq!(limiter.strike(6000, 3, 60000), Some(120000));
+
+        let mut limiter = Limiter::new();
+        limiter.offences = 40;
+        assert_eq!(limiter.strike(1000, 1, 60000), Some(MAX_MUTE));
+    }
+
+    #[test]
+    fn strikes_are_forgotten_after_a_while() {
+        let mut limiter = Limiter::new();
+        assert_eq!(limiter.strike(1000, 2, 60000), None);
+        assert_eq!(limiter.strike(2000 + STRIKE_WINDOW, 2, 60000), None);
+        assert_eq!(limiter.strike(3000 + STRIKE_WINDOW, 2, 60000), Some(60000));
+        assert_eq!(Limiter::new().strike(1000, 0, 60000), None);
+    }
+
+    #[test]
+    fn limiters_go_idle_once_unused() {
+        let limits = limits(1, 1000);
+        let mut limiter = Limiter::new();
+        limiter.allow(&limits, "message", 5000);
+        assert!(!limiter.idle(5000 + IDLE));
+        assert!(limiter.idle(5001 + IDLE));
+    }
+}
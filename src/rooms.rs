@@ -0,0 +1,198 @@
+use std::ascii::AsciiExt;
+use std::collections::HashMap;
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use hyper::uri::RequestUri;
+
+use channel::{self, ChanMessage, Reaper};
+use clock;
+use config::Config;
+use media::Providers;
+use playlists::Store;
+use timer::Timer;
+
+/// The room clients end up in when they connect to `/`.
+pub const DEFAULT_ROOM: &'static str = "lobby";
+
+/// A way into a running room. A room started on demand isn't closed while
+/// anyone holds one, so a client on its way in doesn't find it gone.
+pub struct Room {
+    sender: mpsc::Sender<ChanMessage>,
+    holders: Arc<AtomicUsize>,
+}
+
+impl Room {
+    fn new(sender: mpsc::Sender<ChanMessage>, holders: Arc<AtomicUsize>) -> Room {
+        holders.fetch_add(1, Ordering::SeqCst);
+        Room {
+            sender: sender,
+            holders: holders,
+        }
+    }
+
+    /// Sends `msg` to the room, failing if it has closed.
+    pub fn send(&self, msg: ChanMessage) -> Result<(), mpsc::SendError<ChanMessage>> {
+        self.sender.send(msg)
+    }
+}
+
+impl Clone for Room {
+    fn clone(&self) -> Room {
+        Room::new(self.sender.clone(), self.holders.clone())
+    }
+}
+
+impl Drop for Room {
+    fn drop(&mut self) {
+        self.holders.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+struct Running {
+    sender: mpsc::Sender<ChanMessage>,
+    // how many `Room`s there are for it
+    holders: Arc<AtomicUsize>,
+}
+
+/// All the running rooms, keyed by slug.
+pub struct Rooms {
+    timebase: clock::Timebase,
+    timer: Timer,
+    config: Config,
+    providers: Arc<Providers>,
+    playlists: Arc<Store>,
+    // shared with the rooms started on demand, which take themselves out
+    // when they close
+    running: Arc<Mutex<HashMap<String, Running>>>,
+}
+
+impl Rooms {
+    pub fn new(timebase: clock::Timebase,
+               timer: Timer,
+               config: Config,
+               providers: Arc<Providers>,
+               playlists: Arc<Store>) -> Rooms {
+        Rooms {
+            timebase: timebase,
+            timer: timer,
+            config: config,
+            providers: providers,
+            playlists: playlists,
+            running: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Starts every room listed in the config. They run for as long as the
+    /// server does, however many there are.
+    pub fn start_listed(&mut self) {
+        let rooms = self.config.rooms.clone();
+        let mut running = self.running.lock().unwrap();
+        for room in rooms.into_iter() {
+            info!("starting room {:?}", room.slug);
+            let slug = room.slug.clone();
+            let sender = channel::start_channel(self.timebase,
+                                                self.timer.clone(),
+                                                self.providers.clone(),
+                                                self.playlists.clone(),
+                                                room,
+                                                None);
+            running.insert(slug, Running {
+                sender: sender,
+                holders: Arc::new(AtomicUsize::new(0)),
+            });
+        }
+    }
+
+    /// The room named by `slug`, if it's running.
+    pub fn get(&self, slug: &str) -> Option<Room> {
+        let running = self.running.lock().unwrap();
+        running.get(slug).map(|room| Room::new(room.sender.clone(), room.holders.clone()))
+    }
+
+    /// Checks that the room named by `slug` is running or could be started,
+    /// saying why not if it can't.
+    pub fn check(&self, slug: &str) -> Result<(), String> {
+        let running = self.running.lock().unwrap();
+        if running.contains_key(slug) {
+            return Ok(());
+        }
+        if self.config.room(slug).is_none() {
+            return Err(format!("no such room: {}", slug));
+        }
+        if self.config.max_rooms <= running.len() {
+            return Err("too many rooms are open".to_string());
+        }
+        Ok(())
+    }
+
+    /// Finds the room named by `slug`, starting it if it isn't running yet
+    /// and there's room for it. Rooms which aren't listed in the config can
+    /// only be started if rooms may be created on demand, and close again
+    /// once they've been empty for `empty_room_ttl` seconds.
+    pub fn get_or_start(&mut self, slug: &str) -> Result<Room, String> {
+        if let Some(room) = self.get(slug) {
+            return Ok(room);
+        }
+        try!(self.check(slug));
+        let room = self.config.room(slug).unwrap();
+        let holders = Arc::new(AtomicUsize::new(0));
+        let reaper = {
+            let running = self.running.clone();
+            let holders = holders.clone();
+            let slug = slug.to_string();
+            Reaper {
+                after: self.config.empty_room_ttl as u64 * 1000,
+                close: Box::new(move || {
+                    let mut running = running.lock().unwrap();
+                    // anyone holding a Room may be about to send it a client
+                    if holders.load(Ordering::SeqCst) != 0 {
+                        return false;
+                    }
+                    info!("closing room {:?}", slug);
+                    running.remove(&slug);
+                    true
+                }),
+            }
+        };
+        info!("starting room {:?}", slug);
+        let sender = channel::start_channel(self.timebase,
+                                            self.timer.clone(),
+                                            self.providers.clone(),
+                                            self.playlists.clone(),
+                                            room,
+                                            Some(reaper));
+        let found = Room::new(sender.clone(), holders.clone());
+        self.running.lock().unwrap().insert(slug.to_string(), Running {
+            sender: sender,
+            holders: holders,
+        });
+        Ok(found)
+    }
+}
+
+/// Picks the room slug out of the path of a websocket handshake, so that
+/// `/` means the default room and `/some-room?x=y` means `some-room`.
+pub fn slug_from_uri(uri: &RequestUri) -> Option<String> {
+    let path = match *uri {
+        RequestUri::AbsolutePath(ref path) => path,
+        _ => return None,
+    };
+    let path = match path.find('?') {
+        Some(idx) => &path[..idx],
+        None => &path[..],
+    };
+    let slug = path.trim_matches('/');
+    if slug.is_empty() {
+        return Some(DEFAULT_ROOM.to_string());
+    }
+    normalize_slug(slug)
+}
+
+/// Slugs are case-insensitive and limited to ascii alphanumerics, `-` and `_`.
+pub fn normalize_slug(slug: &str) -> Option<String> {
+    let valid = slug.chars().all(|c| c.is_alphanumeric() && c.is_ascii() || c == '-' || c == '_');
+    if slug.is_empty() || !valid {
+        return None;
+    }
+    Some(slug.to_ascii_lowercase())
+}
@@ -0,0 +1,37 @@
+use std::net::IpAddr;
+use rand::{OsRng, Rng};
+use rustc_serialize::base64::{ToBase64, URL_SAFE};
+
+/// Makes a new resume token: 24 bytes from the OS's random source, which
+/// is as good as a password for as long as the session lasts.
+pub fn new_token() -> String {
+    let mut bytes = [0u8; 24];
+    let mut rng = OsRng::new().unwrap();
+    rng.fill_bytes(&mut bytes);
+    bytes.to_base64(URL_SAFE)
+}
+
+/// What a room remembers about a user's connection, so that they can pick
+/// up where they left off if it drops.
+pub struct Session {
+    pub token: String,
+    // the connection currently attached to the session. Readers of
+    // connections which have since been replaced still report their
+    // disconnects, which must not detach the session.
+    pub conn: u64,
+    // the address of that connection
+    pub ip: IpAddr,
+    // whether the session is waiting out its grace period
+    pub detached: bool,
+}
+
+impl Session {
+    pub fn new(conn: u64, ip: IpAddr) -> Session {
+        Session {
+            token: new_token(),
+            conn: conn,
+            ip: ip,
+            detached: false,
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::{mpsc, Arc, Mutex, Condvar};
+use std::thread;
+use std::time::Duration;
+use time;
+
+use channel::ChanMessage;
+
+struct Entry {
+    deadline_ns: u64,
+    // breaks ties between equal deadlines in the order they were scheduled
+    seq: u64,
+    tx: mpsc::Sender<ChanMessage>,
+    msg: ChanMessage,
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Entry) -> bool {
+        self.deadline_ns == other.deadline_ns && self.seq == other.seq
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Entry) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    // reversed, so the BinaryHeap yields the earliest deadline first
+    fn cmp(&self, other: &Entry) -> Ordering {
+        (other.deadline_ns, other.seq).cmp(&(self.deadline_ns, self.seq))
+    }
+}
+
+struct State {
+    pending: BinaryHeap<Entry>,
+    next_seq: u64,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    cond: Condvar,
+}
+
+/// Delivers messages to channels after a delay. Every timer in the process
+/// is kept by a single thread, rather than each one sleeping on its own.
+#[derive(Clone)]
+pub struct Timer {
+    shared: Arc<Shared>,
+}
+
+impl Timer {
+    pub fn start() -> Timer {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                pending: BinaryHeap::new(),
+                next_seq: 0,
+            }),
+            cond: Condvar::new(),
+        });
+        let thread_shared = shared.clone();
+        thread::spawn(move || timer_thread(thread_shared));
+        Timer { shared: shared }
+    }
+
+    /// Sends `msg` down `tx` once `delay_ms` milliseconds have passed.
+    pub fn schedule(&self, delay_ms: u64, tx: mpsc::Sender<ChanMessage>, msg: ChanMessage) {
+        let mut state = self.shared.state.lock().unwrap();
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.pending.push(Entry {
+            deadline_ns: time::precise_time_ns().saturating_add(delay_ms.saturating_mul(1_000_000)),
+            seq: seq,
+            tx: tx,
+            msg: msg,
+        });
+        self.shared.cond.notify_one();
+    }
+}
+
+fn timer_thread(shared: Arc<Shared>) {
+    let mut state = shared.state.lock().unwrap();
+    loop {
+        let now = time::precise_time_ns();
+        let wait_ns = match state.pending.peek() {
+            Some(entry) if entry.deadline_ns <= now => 0,
+            Some(entry) => entry.deadline_ns - now,
+            None => {
+                state = shared.cond.wait(state).unwrap();
+                continue;
+            }
+        };
+        if wait_ns > 0 {
+            let timeout = Duration::from_millis(wait_ns / 1_000_000 + 1);
+            let (guard, _) = shared.cond.wait_timeout(state, timeout).unwrap();
+            state = guard;
+            continue;
+        }
+        let entry = state.pending.pop().unwrap();
+        // the channel may have gone away in the meantime
+        let _ = entry.tx.send(entry.msg);
+    }
+}
@@ -0,0 +1,248 @@
+//! Plain HTTP alongside the websocket, for what's awkward to pass through
+//! it: library tracks to stream, and playlists as files to import and
+//! export.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Read};
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use hyper;
+use hyper::header::{Authorization, Bearer, ContentType};
+use hyper::method::Method;
+use hyper::server::{Handler, Listening, Request, Response, Server};
+use hyper::status::StatusCode;
+use hyper::uri::RequestUri;
+use serde_json;
+
+use api::PlaylistFormat;
+use auth::{AuthError, Authenticator};
+use clock;
+use form;
+use library::{self, Library};
+use media::Providers;
+use nicks;
+use playlists::{Import, PlaylistError, Store};
+use ratelimit::{Limiter, RateLimit};
+
+const LIBRARY_PREFIX: &'static str = "/library/";
+const PLAYLISTS_PREFIX: &'static str = "/playlists/";
+
+// largest playlist accepted for import, in bytes
+const MAX_IMPORT_LEN: u64 = 1024 * 1024;
+
+// most imports looked up at once; each keeps a worker waiting on it, and
+// the rest are needed for streaming
+const MAX_IMPORTS: usize = 2;
+
+/// Why a request couldn't be served.
+struct Failure(StatusCode, String);
+
+impl From<PlaylistError> for Failure {
+    fn from(err: PlaylistError) -> Failure {
+        match err {
+            PlaylistError::Invalid(msg) => Failure(StatusCode::BadRequest, msg),
+            PlaylistError::NotFound(msg) => Failure(StatusCode::NotFound, msg),
+            PlaylistError::LimitReached(msg) => Failure(StatusCode::Forbidden, msg),
+        }
+    }
+}
+
+fn failure(status: StatusCode, msg: &str) -> Failure {
+    Failure(status, msg.to_string())
+}
+
+fn content_type(format: PlaylistFormat) -> &'static str {
+    match format {
+        PlaylistFormat::Json => "application/json",
+        PlaylistFormat::M3u => "audio/x-mpegurl",
+        PlaylistFormat::Csv => "text/csv",
+    }
+}
+
+fn send(mut res: Response, status: StatusCode, mime: &str, body: &str) -> io::Result<()> {
+    *res.status_mut() = status;
+    res.headers_mut().set(ContentType(mime.parse().unwrap()));
+    res.send(body.as_bytes())
+}
+
+/// The format asked for with the `format` query parameter, JSON if none.
+fn requested_format(query: &[(String, String)]) -> Result<PlaylistFormat, Failure> {
+    match query.iter().find(|&&(ref key, _)| key == "format") {
+        Some(&(_, ref name)) => PlaylistFormat::from_name(name)
+            .map_err(|_| Failure(StatusCode::BadRequest, format!("no such format as {:?}", name))),
+        None => Ok(PlaylistFormat::Json),
+    }
+}
+
+/// Serves library tracks at `/library/<id>`, and the playlists of whoever
+/// presents a bearer token at `/playlists`.
+pub struct Web {
+    pub timebase: clock::Timebase,
+    pub library: Option<Arc<Library>>,
+    pub playlists: Arc<Store>,
+    pub providers: Arc<Providers>,
+    pub authenticator: Option<Arc<Authenticator>>,
+    // longest track accepted on import, in milliseconds
+    pub max_duration: u64,
+    // the room defaults, of which only `playlist_import` applies here
+    pub rate_limits: BTreeMap<String, RateLimit>,
+    // by owner, and kept until they've been idle a while
+    pub limiters: Mutex<HashMap<String, Limiter>>,
+    // imports being looked up
+    pub imports: AtomicUsize,
+}
+
+/// A place among the `MAX_IMPORTS` imports looked up at once, given up when
+/// it's dropped.
+struct ImportSlot<'a>(&'a AtomicUsize);
+
+impl<'a> ImportSlot<'a> {
+    fn take(imports: &'a AtomicUsize) -> Option<ImportSlot<'a>> {
+        if MAX_IMPORTS <= imports.fetch_add(1, Ordering::SeqCst) {
+            imports.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(ImportSlot(imports))
+    }
+}
+
+impl<'a> Drop for ImportSlot<'a> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Web {
+    /// Whose playlists a request is for, going by its bearer token.
+    fn owner(&self, req: &Request) -> Result<String, Failure> {
+        let authenticator = match self.authenticator {
+            Some(ref authenticator) => authenticator,
+            None => return Err(failure(StatusCode::Forbidden, "authentication is not enabled")),
+        };
+        let token = match req.headers.get::<Authorization<Bearer>>() {
+            Some(auth) => auth.0.token.clone(),
+            None => return Err(failure(StatusCode::Unauthorized, "a bearer token is needed")),
+        };
+        match authenticator.authenticate(&token) {
+            Ok(identity) => Ok(nicks::nick_key(&identity.nick)),
+            Err(AuthError::Rejected(msg)) => Err(Failure(StatusCode::Unauthorized, msg)),
+            Err(AuthError::Unavailable(msg)) => {
+                warn!("unable to check token: {}", msg);
+                Err(failure(StatusCode::ServiceUnavailable, "unable to check token"))
+            },
+        }
+    }
+
+    /// Takes an import out of `owner`'s allowance.
+    fn allow_import(&self, owner: &str) -> Result<(), Failure> {
+        let now = self.timebase.now();
+        let mut limiters = self.limiters.lock().unwrap();
+        let idle: Vec<String> = limiters.iter()
+            .filter(|&(_, limiter)| limiter.idle(now))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in idle.iter() {
+            limiters.remove(key);
+        }
+        let limiter = limiters.entry(owner.to_string()).or_insert_with(Limiter::new);
+        if !limiter.allow(&self.rate_limits, "playlist_import", now) {
+            return Err(failure(StatusCode::TooManyRequests, "slow down"));
+        }
+        Ok(())
+    }
+
+    fn export(&self, req: &Request, id: &str, query: &[(String, String)]) -> Result<(PlaylistFormat, String), Failure> {
+        let owner = try!(self.owner(req));
+        let id = try!(id.parse().map_err(|_| failure(StatusCode::NotFound, "no such playlist")));
+        let format = try!(requested_format(query));
+        let data = try!(self.playlists.export(&owner, id, format));
+        Ok((format, data))
+    }
+
+    /// Imports the playlist in the body of `req`. The tracks are looked up
+    /// on a thread of their own, which the worker waits on for the report.
+    fn import(&self, req: &mut Request, query: &[(String, String)]) -> Result<String, Failure> {
+        let owner = try!(self.owner(req));
+        try!(self.allow_import(&owner));
+        let format = try!(requested_format(query));
+        let mut data = String::new();
+        if let Err(err) = req.by_ref().take(MAX_IMPORT_LEN + 1).read_to_string(&mut data) {
+            return Err(Failure(StatusCode::BadRequest, err.to_string()));
+        }
+        if MAX_IMPORT_LEN < data.len() as u64 {
+            return Err(failure(StatusCode::PayloadTooLarge, "the playlist is too long"));
+        }
+        let param = |name: &str| query.iter().find(|&&(ref key, _)| key == name).map(|&(_, ref value)| value.clone());
+        let import = Import {
+            format: format,
+            data: data,
+            name: param("name"),
+            dry_run: param("dry_run").map_or(false, |value| value == "true" || value == "1"),
+            max_duration: self.max_duration,
+        };
+        let _slot = match ImportSlot::take(&self.imports) {
+            Some(slot) => slot,
+            None => return Err(failure(StatusCode::ServiceUnavailable, "too many imports underway")),
+        };
+        let (tx, rx) = mpsc::channel();
+        let playlists = self.playlists.clone();
+        let providers = self.providers.clone();
+        thread::spawn(move || {
+            let _ = tx.send(playlists.import(&providers, &owner, &import));
+        });
+        let result = try!(rx.recv().map_err(|_| failure(StatusCode::InternalServerError, "the import failed")));
+        let mut report = try!(result);
+        report.when = self.timebase.now();
+        Ok(serde_json::to_string(&report).unwrap())
+    }
+}
+
+impl Handler for Web {
+    fn handle(&self, mut req: Request, res: Response) {
+        let uri = match req.uri {
+            RequestUri::AbsolutePath(ref path) => path.clone(),
+            _ => String::new(),
+        };
+        let (path, query) = match uri.find('?') {
+            Some(idx) => (&uri[..idx], form::parse(&uri[idx + 1..])),
+            None => (&uri[..], Vec::new()),
+        };
+        let result = match (req.method.clone(), path) {
+            (Method::Get, _) if path.starts_with(LIBRARY_PREFIX) => {
+                let id = form::decode(&path[LIBRARY_PREFIX.len()..]);
+                match self.library.as_ref().and_then(|library| library.get(&id)) {
+                    Some(track) => library::stream::send(track, req.headers.get(), res),
+                    None => send(res, StatusCode::NotFound, "text/plain", "no such track"),
+                }
+            },
+            (Method::Get, _) if path.starts_with(PLAYLISTS_PREFIX) => {
+                match self.export(&req, &path[PLAYLISTS_PREFIX.len()..], &query) {
+                    Ok((format, data)) => send(res, StatusCode::Ok, content_type(format), &data),
+                    Err(Failure(status, msg)) => send(res, status, "text/plain", &msg),
+                }
+            },
+            (Method::Post, "/playlists") => {
+                match self.import(&mut req, &query) {
+                    Ok(report) => send(res, StatusCode::Ok, "application/json", &report),
+                    Err(Failure(status, msg)) => send(res, status, "text/plain", &msg),
+                }
+            },
+            (_, "/playlists") => send(res, StatusCode::MethodNotAllowed, "text/plain", ""),
+            (_, _) if path.starts_with(LIBRARY_PREFIX) || path.starts_with(PLAYLISTS_PREFIX) => {
+                send(res, StatusCode::MethodNotAllowed, "text/plain", "")
+            },
+            _ => send(res, StatusCode::NotFound, "text/plain", "not found"),
+        };
+        if let Err(err) = result {
+            debug!("error serving {} to {}: {}", path, req.remote_addr, err);
+        }
+    }
+}
+
+/// Starts serving on `bind`. The server stops when the returned
+/// `Listening` is dropped.
+pub fn serve(bind: &str, web: Web) -> hyper::Result<Listening> {
+    let server = try!(Server::http(bind));
+    server.handle(web)
+}